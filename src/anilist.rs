@@ -1,10 +1,12 @@
 use std::{collections::HashMap, time::Duration};
 
-use reqwest::{Client, Url};
+use reqwest::{Client, StatusCode, Url, header::RETRY_AFTER};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::debug;
 
+use crate::config::OutboundConfig;
+
 const MAX_IDS_PER_REQUEST: usize = 50;
 
 const MEDIA_QUERY: &str = r#"
@@ -12,8 +14,14 @@ query MediaById($idIn: [Int], $perPage: Int) {
   Page(perPage: $perPage) {
     media(id_in: $idIn) {
       id
-      type
       format
+      title {
+        english
+        romaji
+        native
+      }
+      synonyms
+      genres
     }
   }
 }
@@ -23,16 +31,23 @@ query MediaById($idIn: [Int], $perPage: Int) {
 pub struct AniListClient {
     http: Client,
     endpoint: Url,
+    unknown_format_as: Option<MediaFormat>,
 }
 
 impl AniListClient {
-    pub fn new(endpoint: Url, timeout: Duration) -> anyhow::Result<Self> {
-        let http = Client::builder()
-            .timeout(timeout)
-            .user_agent(format!("seadexerr/{}", env!("CARGO_PKG_VERSION")))
-            .build()?;
+    pub fn new(
+        endpoint: Url,
+        timeout: Duration,
+        unknown_format_as: Option<MediaFormat>,
+        outbound: &OutboundConfig,
+    ) -> anyhow::Result<Self> {
+        let http = outbound.client_builder(timeout)?.build()?;
 
-        Ok(Self { http, endpoint })
+        Ok(Self {
+            http,
+            endpoint,
+            unknown_format_as,
+        })
     }
 
     pub async fn fetch_media(
@@ -62,8 +77,15 @@ impl AniListClient {
                 .post(self.endpoint.clone())
                 .json(&request)
                 .send()
-                .await?
-                .error_for_status()?;
+                .await?;
+
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                return Err(AniListError::RateLimited {
+                    retry_after: retry_after(&response),
+                });
+            }
+
+            let response = response.error_for_status()?;
 
             let payload: GraphqlResponse = response.json().await?;
 
@@ -86,12 +108,32 @@ impl AniListClient {
             for media in page.media.into_iter() {
                 let format = match media.format.as_deref().and_then(MediaFormat::from_str) {
                     Some(format) => format,
-                    None => continue,
+                    None => match &self.unknown_format_as {
+                        Some(default) => {
+                            debug!(
+                                anilist_id = media.id,
+                                format = media.format.as_deref().unwrap_or("null"),
+                                assumed = ?default,
+                                "defaulted unknown AniList format"
+                            );
+                            default.clone()
+                        }
+                        None => continue,
+                    },
                 };
 
+                let titles = media.title.unwrap_or_default();
+                let title = titles.english.clone().or_else(|| titles.romaji.clone());
+
                 result.entry(media.id).or_insert(AniListMedia {
                     id: media.id,
                     format,
+                    title,
+                    romaji: titles.romaji,
+                    english: titles.english,
+                    native: titles.native,
+                    synonyms: media.synonyms.unwrap_or_default(),
+                    genres: media.genres.unwrap_or_default(),
                 });
             }
 
@@ -102,6 +144,16 @@ impl AniListClient {
     }
 }
 
+/// Reads a `Retry-After` header expressed in seconds.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MediaFormat {
     Tv,
@@ -140,6 +192,44 @@ impl MediaFormat {
 pub struct AniListMedia {
     pub id: i64,
     pub format: MediaFormat,
+    /// English title, falling back to romaji.
+    pub title: Option<String>,
+    pub romaji: Option<String>,
+    pub english: Option<String>,
+    pub native: Option<String>,
+    pub synonyms: Vec<String>,
+    pub genres: Vec<String>,
+}
+
+impl AniListMedia {
+    /// Every known title for the media: romaji, english, native, then synonyms.
+    pub fn titles(&self) -> impl Iterator<Item = &str> {
+        [&self.romaji, &self.english, &self.native]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .chain(self.synonyms.iter().map(String::as_str))
+    }
+}
+
+/// Whether `query` names `media`, comparing case-insensitively and ignoring punctuation
+/// and whitespace differences against any of its titles or synonyms.
+pub fn matches_title(media: &AniListMedia, query: &str) -> bool {
+    let query = normalize_title(query);
+    if query.is_empty() {
+        return false;
+    }
+
+    media.titles().any(|title| normalize_title(title) == query)
+}
+
+fn normalize_title(value: &str) -> String {
+    value
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 #[derive(Debug, Serialize)]
@@ -177,9 +267,23 @@ struct GraphqlPage {
 #[derive(Debug, Deserialize)]
 struct GraphqlMedia {
     id: i64,
-    #[serde(rename = "type")]
-    media_type: Option<String>,
     format: Option<String>,
+    #[serde(default)]
+    title: Option<GraphqlTitle>,
+    #[serde(default)]
+    synonyms: Option<Vec<String>>,
+    #[serde(default)]
+    genres: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct GraphqlTitle {
+    #[serde(default)]
+    english: Option<String>,
+    #[serde(default)]
+    romaji: Option<String>,
+    #[serde(default)]
+    native: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -197,4 +301,38 @@ pub enum AniListError {
     MissingData,
     #[error("AniList GraphQL error(s): {0}")]
     Graphql(String),
+    #[error("AniList rate limit exceeded")]
+    RateLimited { retry_after: Option<Duration> },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_match_ignoring_case_punctuation_and_spacing() {
+        let media = AniListMedia {
+            id: 21,
+            format: MediaFormat::Tv,
+            title: Some("One Piece".to_owned()),
+            romaji: Some("ONE PIECE".to_owned()),
+            english: Some("One Piece".to_owned()),
+            native: Some("ワンピース".to_owned()),
+            synonyms: vec!["OP".to_owned()],
+            genres: Vec::new(),
+        };
+
+        for query in [
+            "one piece",
+            "One-Piece!",
+            "  ONE   PIECE ",
+            "ワンピース",
+            "op",
+        ] {
+            assert!(matches_title(&media, query), "{query}");
+        }
+        for query in ["One Piece Film", "", "--"] {
+            assert!(!matches_title(&media, query), "{query}");
+        }
+    }
 }
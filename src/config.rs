@@ -1,32 +1,125 @@
-use std::{env, net::SocketAddr, path::PathBuf, time::Duration};
+use std::{
+    collections::HashMap,
+    env,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
-use reqwest::Url;
+use reqwest::{ClientBuilder, NoProxy, Proxy, Url};
+use serde_json::json;
+
+use crate::anilist::MediaFormat;
+use crate::mapping::SeasonStrategy;
+use crate::release_filter::SpecialsInPacks;
+use crate::releases::{DEFAULT_NYAA_DOWNLOAD_TEMPLATE, ScoreWeights};
+use crate::torznab::{CommentsSource, GuidSource};
+
+const MIN_MAPPING_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Bounds applied to every upstream timeout; values outside are clamped with a warning.
+const MIN_TIMEOUT_SECS: u64 = 1;
+const MAX_TIMEOUT_SECS: u64 = 300;
 
 #[derive(Clone, Debug)]
 pub struct AppConfig {
     pub listen_addr: SocketAddr,
+    pub dual_stack: bool,
+    pub listen_uds: Option<PathBuf>,
+    pub admin_addr: Option<SocketAddr>,
+    pub tls_cert_path: Option<PathBuf>,
+    pub tls_key_path: Option<PathBuf>,
     pub public_base_url: Option<Url>,
+    pub trust_forwarded: bool,
     pub releases_base_url: Url,
     pub releases_timeout: Duration,
+    pub releases_connect_timeout: Option<Duration>,
+    pub releases_token: Option<String>,
+    pub trackers: Vec<String>,
+    pub releases_expand_key: String,
+    /// Download URL for nyaa torrents, with the nyaa id substituted for `{id}`.
+    pub nyaa_download_template: String,
     pub data_path: PathBuf,
-    pub mapping_source_url: Url,
+    /// Directory for downloaded mappings and title caches; defaults to `data_path`.
+    pub cache_path: PathBuf,
+    pub mapping_source_urls: Vec<Url>,
     pub mapping_refresh_interval: Duration,
+    pub mapping_refresh_jitter: f64,
+    pub mapping_index_cache: bool,
+    pub mapping_watch: bool,
+    pub season_strategy: SeasonStrategy,
+    /// Treat the tv-search `ep` param as an absolute episode number.
+    pub absolute_numbering: bool,
+    pub specials_in_packs: SpecialsInPacks,
+    /// Fewest files a torrent needs to be offered as a tv season pack.
+    pub min_pack_files: usize,
     pub mapping_timeout: Duration,
     pub application_title: String,
     pub application_description: String,
+    pub fallback_title_template: Option<String>,
     pub default_limit: usize,
+    pub max_limit: usize,
+    /// Page size for the generic search (RSS sync) when the request has no `limit`.
+    pub rss_limit: usize,
+    pub rss_max_age: Option<Duration>,
+    /// Most Sonarr/Radarr title lookups the generic search runs at once.
+    pub arr_concurrency: usize,
+    /// Page size for tv, movie and AniList searches when the request has no `limit`.
+    pub search_limit: usize,
+    pub outbound: OutboundConfig,
+    pub nyaa_seeders: bool,
+    pub min_seeders: u32,
+    pub best_only: bool,
+    pub best_only_fallback: bool,
+    pub best_as_freeleech: bool,
+    pub empty_as_error: bool,
+    /// Leave releases marked incomplete (partial batches) out of tv-search results.
+    pub skip_incomplete: bool,
+    /// Release groups or file-name keywords whose releases are never returned.
+    pub blocklist: Vec<String>,
+    /// When non-empty, only releases from these groups are returned.
+    pub group_allowlist: Vec<String>,
+    pub trusted_groups: Vec<String>,
+    pub score_weights: ScoreWeights,
+    pub pubdate_fallback: bool,
+    /// Send weak ETags on the generic search feed and answer matching `If-None-Match`
+    /// requests with `304 Not Modified`.
+    pub feed_etag: bool,
+    pub comments_source: CommentsSource,
+    pub guid_source: GuidSource,
+    pub title_cache_max: usize,
+    /// Seed the Sonarr/Radarr title caches from their monitored libraries.
+    pub prewarm: bool,
     pub anilist_base_url: Url,
     pub anilist_timeout: Duration,
+    /// Format assumed for AniList media whose format is missing or unrecognised; such
+    /// media is dropped when unset.
+    pub unknown_format_as: Option<MediaFormat>,
+    /// AniList ids forced to be treated as TV or movie regardless of their AniList format.
+    pub format_overrides: HashMap<i64, MediaFormat>,
+    /// TVmaze API root used to translate `tvmazeid`/`rid` searches into tvdb ids.
+    pub tvmaze_base_url: Url,
     pub sonarr: Option<SonarrConfig>,
     pub radarr: Option<RadarrConfig>,
 }
 
+#[derive(Clone, Debug)]
+pub struct OutboundConfig {
+    pub user_agent: String,
+    pub http_proxy: Option<Url>,
+    pub https_proxy: Option<Url>,
+    pub no_proxy: Option<String>,
+}
+
 #[derive(Clone, Debug)]
 pub struct SonarrConfig {
     pub url: Url,
     pub api_key: String,
     pub timeout: Duration,
+    pub insecure: bool,
+    pub api_key_in_query: bool,
+    pub library_only: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -34,160 +127,963 @@ pub struct RadarrConfig {
     pub url: Url,
     pub api_key: String,
     pub timeout: Duration,
+    pub insecure: bool,
+    pub api_key_in_query: bool,
 }
 
 impl AppConfig {
+    /// The loaded configuration as JSON for support requests, with API keys, tokens and
+    /// URL credentials replaced by `***`.
+    pub fn redacted(&self) -> serde_json::Value {
+        let secret = |value: &str| if value.is_empty() { "" } else { REDACTED };
+        let app = |url: &Url, api_key: &str, timeout: Duration, insecure: bool, in_query: bool| {
+            json!({
+                "url": redact_url(url),
+                "api_key": secret(api_key),
+                "timeout_secs": timeout.as_secs(),
+                "insecure": insecure,
+                "api_key_in_query": in_query,
+            })
+        };
+
+        json!({
+            "listen_addr": self.listen_addr.to_string(),
+            "dual_stack": self.dual_stack,
+            "listen_uds": self.listen_uds,
+            "admin_addr": self.admin_addr.map(|addr| addr.to_string()),
+            "tls_cert_path": self.tls_cert_path,
+            "tls_key_path": self.tls_key_path,
+            "public_base_url": self.public_base_url.as_ref().map(redact_url),
+            "trust_forwarded": self.trust_forwarded,
+            "releases_base_url": redact_url(&self.releases_base_url),
+            "releases_timeout_secs": self.releases_timeout.as_secs(),
+            "releases_connect_timeout_secs": self
+                .releases_connect_timeout
+                .map(|timeout| timeout.as_secs()),
+            "releases_token": self.releases_token.as_deref().map(secret),
+            "trackers": self.trackers,
+            "releases_expand_key": self.releases_expand_key,
+            "nyaa_download_template": self.nyaa_download_template,
+            "data_path": self.data_path,
+            "cache_path": self.cache_path,
+            "mapping_source_urls": self
+                .mapping_source_urls
+                .iter()
+                .map(redact_url)
+                .collect::<Vec<_>>(),
+            "mapping_refresh_secs": self.mapping_refresh_interval.as_secs(),
+            "mapping_refresh_jitter": self.mapping_refresh_jitter,
+            "mapping_index_cache": self.mapping_index_cache,
+            "mapping_watch": self.mapping_watch,
+            "season_strategy": format!("{:?}", self.season_strategy),
+            "absolute_numbering": self.absolute_numbering,
+            "specials_in_packs": format!("{:?}", self.specials_in_packs),
+            "min_pack_files": self.min_pack_files,
+            "mapping_timeout_secs": self.mapping_timeout.as_secs(),
+            "application_title": self.application_title,
+            "application_description": self.application_description,
+            "fallback_title_template": self.fallback_title_template,
+            "default_limit": self.default_limit,
+            "max_limit": self.max_limit,
+            "rss_limit": self.rss_limit,
+            "rss_max_age_hours": self.rss_max_age.map(|age| age.as_secs() / 3600),
+            "arr_concurrency": self.arr_concurrency,
+            "search_limit": self.search_limit,
+            "outbound": {
+                "user_agent": self.outbound.user_agent,
+                "http_proxy": self.outbound.http_proxy.as_ref().map(redact_url),
+                "https_proxy": self.outbound.https_proxy.as_ref().map(redact_url),
+                "no_proxy": self.outbound.no_proxy,
+            },
+            "nyaa_seeders": self.nyaa_seeders,
+            "min_seeders": self.min_seeders,
+            "best_only": self.best_only,
+            "best_only_fallback": self.best_only_fallback,
+            "best_as_freeleech": self.best_as_freeleech,
+            "empty_as_error": self.empty_as_error,
+            "skip_incomplete": self.skip_incomplete,
+            "blocklist": self.blocklist,
+            "group_allowlist": self.group_allowlist,
+            "trusted_groups": self.trusted_groups,
+            "score_weights": self.score_weights,
+            "pubdate_fallback": self.pubdate_fallback,
+            "feed_etag": self.feed_etag,
+            "comments_source": format!("{:?}", self.comments_source),
+            "guid_source": format!("{:?}", self.guid_source),
+            "title_cache_max": self.title_cache_max,
+            "prewarm": self.prewarm,
+            "anilist_base_url": redact_url(&self.anilist_base_url),
+            "anilist_timeout_secs": self.anilist_timeout.as_secs(),
+            "unknown_format_as": self
+                .unknown_format_as
+                .as_ref()
+                .map(|format| format!("{format:?}")),
+            "format_overrides": self
+                .format_overrides
+                .iter()
+                .map(|(id, format)| (id.to_string(), format!("{format:?}")))
+                .collect::<HashMap<_, _>>(),
+            "tvmaze_base_url": redact_url(&self.tvmaze_base_url),
+            "sonarr": self.sonarr.as_ref().map(|sonarr| {
+                let mut value = app(
+                    &sonarr.url,
+                    &sonarr.api_key,
+                    sonarr.timeout,
+                    sonarr.insecure,
+                    sonarr.api_key_in_query,
+                );
+                value["library_only"] = json!(sonarr.library_only);
+                value
+            }),
+            "radarr": self.radarr.as_ref().map(|radarr| app(
+                &radarr.url,
+                &radarr.api_key,
+                radarr.timeout,
+                radarr.insecure,
+                radarr.api_key_in_query,
+            )),
+        })
+    }
+
     pub fn from_env() -> Result<Self> {
-        let host = env::var("SEADEXER_HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
-        let port = env::var("SEADEXER_PORT").unwrap_or_else(|_| "6767".to_string());
+        Self::from_lookup(|name| env::var(name))
+    }
+
+    /// Builds the configuration from `var`, which resolves a variable name the way
+    /// [`env::var`] does; tests pass a map instead of touching the process environment.
+    pub fn from_lookup(var: impl Fn(&str) -> Result<String, env::VarError>) -> Result<Self> {
+        let host = var("SEADEXER_HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
+        let port = var("SEADEXER_PORT").unwrap_or_else(|_| "6767".to_string());
         let port = port
             .parse::<u16>()
             .context("SEADEXER_PORT must be a valid u16 integer")?;
-        let listen_addr: SocketAddr = format!("{host}:{port}")
-            .parse()
-            .context("failed to parse socket address from SEADEXER_HOST and SEADEXER_PORT")?;
+        // Accept bare (`::`) and bracketed (`[::]`) IPv6 hosts alongside IPv4.
+        let host = host.trim();
+        let host = host
+            .strip_prefix('[')
+            .and_then(|value| value.strip_suffix(']'))
+            .unwrap_or(host);
+        let ip = host
+            .parse::<IpAddr>()
+            .context("SEADEXER_HOST must be an IPv4 or IPv6 address")?;
+        let listen_addr = SocketAddr::new(ip, port);
+
+        let dual_stack = var("SEADEXER_DUAL_STACK")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+
+        let listen_uds = var("SEADEXER_LISTEN_UDS")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from);
+
+        let admin_addr = var("SEADEXER_ADMIN_ADDR")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| value.trim().parse::<SocketAddr>())
+            .transpose()
+            .context("SEADEXER_ADMIN_ADDR must be a socket address such as 127.0.0.1:6768")?;
 
-        let raw_base_url = env::var("SEADEXER_RELEASES_BASE_URL")
+        let tls_cert_path = var("SEADEXER_TLS_CERT")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from);
+        let tls_key_path = var("SEADEXER_TLS_KEY")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from);
+
+        let raw_base_url = var("SEADEXER_RELEASES_BASE_URL")
             .unwrap_or_else(|_| "https://releases.moe/api/".to_string());
-        let releases_base_url = parse_root_url(&raw_base_url, "SEADEXER_RELEASES_BASE_URL")?;
+        let releases_base_url =
+            normalize_api_root(parse_root_url(&raw_base_url, "SEADEXER_RELEASES_BASE_URL")?);
+
+        let releases_token = var("SEADEXER_RELEASES_TOKEN")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+
+        let trackers: Vec<String> = var("SEADEXER_TRACKERS")
+            .unwrap_or_else(|_| "Nyaa".to_string())
+            .split(',')
+            .map(|tracker| tracker.trim().to_string())
+            .filter(|tracker| !tracker.is_empty())
+            .collect();
 
-        let data_path = env::var("SEADEXER_DATA_PATH").unwrap_or_else(|_| "data".to_string());
+        let releases_expand_key = var("SEADEXER_RELEASES_EXPAND_KEY")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "trs".to_string());
+        let nyaa_download_template = var("SEADEXER_NYAA_DOWNLOAD_BASE")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| DEFAULT_NYAA_DOWNLOAD_TEMPLATE.to_string());
+
+        let data_path = var("SEADEXER_DATA_PATH").unwrap_or_else(|_| "data".to_string());
         let data_path = PathBuf::from(data_path);
+        let cache_path = var("SEADEXER_CACHE_PATH")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| data_path.clone());
 
-        let raw_mapping_source_url = env::var("SEADEXER_MAPPING_SOURCE_URL").unwrap_or_else(|_| {
+        let raw_mapping_source_url = var("SEADEXER_MAPPING_SOURCE_URL").unwrap_or_else(|_| {
             "https://raw.githubusercontent.com/eliasbenb/PlexAniBridge-Mappings/refs/heads/v2/mappings.json".to_string()
         });
-        let mapping_source_url = Url::parse(&raw_mapping_source_url)
-            .context("SEADEXER_MAPPING_SOURCE_URL must be a valid URL")?;
+        let mapping_source_urls = raw_mapping_source_url
+            .split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| {
+                Url::parse(value).with_context(|| {
+                    format!("SEADEXER_MAPPING_SOURCE_URL entry `{value}` must be a valid URL")
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-        let mapping_refresh_secs = env::var("SEADEXER_MAPPING_REFRESH_SECS")
+        let mapping_refresh_secs = var("SEADEXER_MAPPING_REFRESH_SECS")
             .ok()
             .and_then(|value| value.parse::<u64>().ok())
-            .filter(|value| *value > 0)
             .unwrap_or(21_600);
+        // `0` disables the periodic refresh for mappings managed outside the service
         let mapping_refresh_interval = Duration::from_secs(mapping_refresh_secs);
 
-        let public_base_url = env::var("SEADEXER_PUBLIC_BASE_URL")
+        let mapping_refresh_jitter_percent = var("SEADEXER_MAPPING_REFRESH_JITTER_PERCENT")
             .ok()
-            .map(|value| Url::parse(&value).context("SEADEXER_PUBLIC_BASE_URL must be a valid URL"))
-            .transpose()?;
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(10)
+            .min(50);
+        let mapping_refresh_jitter = f64::from(mapping_refresh_jitter_percent) / 100.0;
+
+        let mapping_index_cache = var("SEADEXER_MAPPING_INDEX_CACHE")
+            .map(|v| v == "true")
+            .unwrap_or(false);
 
-        let timeout_secs = env::var("SEADEXER_RELEASES_TIMEOUT_SECS")
+        let mapping_watch = var("SEADEXER_MAPPING_WATCH")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+
+        let season_strategy = var("SEADEXER_SEASON_STRATEGY")
             .ok()
-            .and_then(|value| value.parse::<u64>().ok())
-            .unwrap_or(10);
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| {
+                SeasonStrategy::parse(&value).with_context(|| {
+                    format!(
+                        "SEADEXER_SEASON_STRATEGY must be `lowest`, `highest` or `all`, \
+                         got `{value}`"
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let absolute_numbering = var("SEADEXER_ABSOLUTE_NUMBERING")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+
+        let specials_in_packs = var("SEADEXER_SPECIALS_IN_PACKS")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| {
+                SpecialsInPacks::parse(&value).with_context(|| {
+                    format!(
+                        "SEADEXER_SPECIALS_IN_PACKS must be `include` or `exclude`, got `{value}`"
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let min_pack_files = var("SEADEXER_MIN_PACK_FILES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(2);
+
+        // normalised to end in `/` so a sub-path like `https://host/seadex` keeps its prefix
+        // when `api` is joined onto it
+        let public_base_url = var("SEADEXER_PUBLIC_BASE_URL")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| parse_root_url(&value, "SEADEXER_PUBLIC_BASE_URL"))
+            .transpose()?;
+        let trust_forwarded = var("SEADEXER_TRUST_FORWARDED")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+
+        let timeout_secs = timeout_secs_from_env(&var, "SEADEXER_RELEASES_TIMEOUT_SECS", 10);
         let releases_timeout = Duration::from_secs(timeout_secs);
 
-        let mapping_timeout_secs = env::var("SEADEXER_MAPPING_TIMEOUT_SECS")
+        let releases_connect_timeout = var("SEADEXER_RELEASES_CONNECT_TIMEOUT")
             .ok()
-            .and_then(|value| value.parse::<u64>().ok())
-            .unwrap_or(timeout_secs);
-        let mapping_timeout = Duration::from_secs(mapping_timeout_secs.max(1));
+            .filter(|value| !value.trim().is_empty())
+            .map(|_| {
+                let secs =
+                    timeout_secs_from_env(&var, "SEADEXER_RELEASES_CONNECT_TIMEOUT", timeout_secs);
+                Duration::from_secs(secs)
+            });
+
+        let mapping_timeout = Duration::from_secs(timeout_secs_from_env(
+            &var,
+            "SEADEXER_MAPPING_TIMEOUT_SECS",
+            timeout_secs,
+        ));
 
-        let application_title =
-            env::var("SEADEXER_TITLE").unwrap_or_else(|_| "Seadexer".to_string());
-        let application_description = env::var("SEADEXER_DESCRIPTION")
+        let application_title = var("SEADEXER_TITLE").unwrap_or_else(|_| "Seadexer".to_string());
+        let application_description = var("SEADEXER_DESCRIPTION")
             .unwrap_or_else(|_| "Indexer bridge for releases.moe".to_string());
+        let fallback_title_template = var("SEADEXER_FALLBACK_TITLE_TEMPLATE")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
 
-        let default_limit = env::var("SEADEXER_DEFAULT_LIMIT")
+        let default_limit = var("SEADEXER_DEFAULT_LIMIT")
             .ok()
             .and_then(|value| value.parse::<usize>().ok())
             .filter(|value| *value > 0)
             .unwrap_or(100);
 
-        let raw_anilist_url = env::var("SEADEXER_ANILIST_BASE_URL")
+        let rss_limit = var("SEADEXER_RSS_LIMIT")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(default_limit);
+        let rss_max_age = var("SEADEXER_RSS_MAX_AGE_HOURS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .and_then(|hours| hours.checked_mul(3600))
+            .map(Duration::from_secs);
+        let arr_concurrency = var("SEADEXER_ARR_CONCURRENCY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(4);
+        let search_limit = var("SEADEXER_SEARCH_LIMIT")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(default_limit);
+
+        let max_limit = var("SEADEXER_MAX_LIMIT")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or_else(|| default_limit.max(rss_limit).max(search_limit).max(100));
+
+        let user_agent = var("SEADEXER_USER_AGENT")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| format!("seadexerr/{}", env!("CARGO_PKG_VERSION")));
+
+        let http_proxy = var("SEADEXER_HTTP_PROXY")
+            .ok()
+            .map(|value| Url::parse(&value).context("SEADEXER_HTTP_PROXY must be a valid URL"))
+            .transpose()?;
+        let https_proxy = var("SEADEXER_HTTPS_PROXY")
+            .ok()
+            .map(|value| Url::parse(&value).context("SEADEXER_HTTPS_PROXY must be a valid URL"))
+            .transpose()?;
+        let no_proxy = var("SEADEXER_NO_PROXY")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+
+        let outbound = OutboundConfig {
+            user_agent,
+            http_proxy,
+            https_proxy,
+            no_proxy,
+        };
+
+        let nyaa_seeders = var("SEADEXER_NYAA_SEEDERS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+
+        let min_seeders = var("SEADEXER_MIN_SEEDERS")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(0);
+
+        let best_only = var("SEADEXER_BEST_ONLY")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let best_only_fallback = var("SEADEXER_BEST_ONLY_FALLBACK")
+            .map(|v| v != "false")
+            .unwrap_or(true);
+        let best_as_freeleech = var("SEADEXER_BEST_AS_FREELEECH")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let empty_as_error = var("SEADEXER_EMPTY_AS_ERROR")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let skip_incomplete = var("SEADEXER_SKIP_INCOMPLETE")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+
+        let blocklist: Vec<String> = var("SEADEXER_BLOCKLIST")
+            .unwrap_or_default()
+            .split(',')
+            .map(|term| term.trim().to_string())
+            .filter(|term| !term.is_empty())
+            .collect();
+
+        let group_allowlist: Vec<String> = var("SEADEXER_GROUP_ALLOWLIST")
+            .unwrap_or_default()
+            .split(',')
+            .map(|group| group.trim().to_string())
+            .filter(|group| !group.is_empty())
+            .collect();
+
+        let trusted_groups: Vec<String> = var("SEADEXER_TRUSTED_GROUPS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|group| group.trim().to_string())
+            .filter(|group| !group.is_empty())
+            .collect();
+
+        let score_weights = ScoreWeights::parse(&var("SEADEXER_SCORE_WEIGHTS").unwrap_or_default())
+            .context("SEADEXER_SCORE_WEIGHTS is invalid")?;
+
+        let pubdate_fallback = var("SEADEXER_PUBDATE_FALLBACK")
+            .map(|v| v != "false")
+            .unwrap_or(true);
+
+        let feed_etag = var("SEADEXER_FEED_ETAG")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+
+        let comments_source = var("SEADEXER_COMMENTS_SOURCE")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| {
+                CommentsSource::parse(&value).with_context(|| {
+                    format!("SEADEXER_COMMENTS_SOURCE must be `seadex` or `nyaa`, got `{value}`")
+                })
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let guid_source = var("SEADEXER_GUID_SOURCE")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| {
+                GuidSource::parse(&value).with_context(|| {
+                    format!("SEADEXER_GUID_SOURCE must be `id` or `infohash`, got `{value}`")
+                })
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let title_cache_max = var("SEADEXER_TITLE_CACHE_MAX")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(5_000);
+
+        let prewarm = var("SEADEXER_PREWARM")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+
+        let raw_anilist_url = var("SEADEXER_ANILIST_BASE_URL")
             .unwrap_or_else(|_| "https://graphql.anilist.co".to_string());
         let anilist_base_url = Url::parse(&raw_anilist_url)
             .context("SEADEXER_ANILIST_BASE_URL must be a valid URL")?;
 
-        let anilist_timeout_secs = env::var("SEADEXER_ANILIST_TIMEOUT_SECS")
+        let anilist_timeout = Duration::from_secs(timeout_secs_from_env(
+            &var,
+            "SEADEXER_ANILIST_TIMEOUT_SECS",
+            timeout_secs,
+        ));
+
+        let unknown_format_as = var("SEADEXER_UNKNOWN_FORMAT_AS")
             .ok()
-            .and_then(|value| value.parse::<u64>().ok())
-            .unwrap_or(timeout_secs);
-        let anilist_timeout = Duration::from_secs(anilist_timeout_secs.max(1));
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| {
+                parse_format_kind(&value).with_context(|| {
+                    format!("SEADEXER_UNKNOWN_FORMAT_AS must be `tv` or `movie`, got `{value}`")
+                })
+            })
+            .transpose()?;
+
+        let raw_tvmaze_url = var("SEADEXER_TVMAZE_BASE_URL")
+            .unwrap_or_else(|_| "https://api.tvmaze.com/".to_string());
+        let tvmaze_base_url = parse_root_url(&raw_tvmaze_url, "SEADEXER_TVMAZE_BASE_URL")?;
 
-        let sonarr_enabled = env::var("SEADEXER_SONARR_ENABLED")
+        let mut format_overrides = HashMap::new();
+        for entry in var("SEADEXER_FORMAT_OVERRIDES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (anilist_id, format) = entry
+                .split_once(':')
+                .and_then(|(id, format)| Some((id.trim().parse::<i64>().ok()?, format)))
+                .and_then(|(id, format)| Some((id, parse_format_kind(format)?)))
+                .with_context(|| {
+                    format!(
+                        "SEADEXER_FORMAT_OVERRIDES entries must look like `12345:tv` or \
+                         `12345:movie`, got `{entry}`"
+                    )
+                })?;
+            format_overrides.insert(anilist_id, format);
+        }
+
+        let sonarr_enabled = var("SEADEXER_SONARR_ENABLED")
             .map(|v| v != "false")
             .unwrap_or(true);
 
         let sonarr = if sonarr_enabled {
-            let raw_sonarr_url = env::var("SONARR_BASE_URL")
-                .unwrap_or_else(|_| "http://localhost:8989".to_string());
+            let raw_sonarr_url =
+                var("SONARR_BASE_URL").unwrap_or_else(|_| "http://localhost:8989".to_string());
             let sonarr_url = parse_root_url(&raw_sonarr_url, "SONARR_BASE_URL")?;
 
-            let sonarr_api_key =
-                env::var("SONARR_API_KEY").context("Missing SONARR_API_KEY variable")?;
+            let sonarr_api_key = var("SONARR_API_KEY").unwrap_or_default();
+
+            let sonarr_timeout = Duration::from_secs(timeout_secs_from_env(
+                &var,
+                "SONARR_TIMEOUT_SECS",
+                timeout_secs,
+            ));
 
-            let sonarr_timeout_secs = env::var("SONARR_TIMEOUT_SECS")
-                .ok()
-                .and_then(|value| value.parse::<u64>().ok())
-                .unwrap_or(timeout_secs);
-            let sonarr_timeout = Duration::from_secs(sonarr_timeout_secs.max(1));
+            let sonarr_insecure = var("SONARR_INSECURE").map(|v| v == "true").unwrap_or(false);
+
+            let sonarr_api_key_in_query = var("SONARR_APIKEY_IN_QUERY")
+                .map(|v| v == "true")
+                .unwrap_or(false);
+
+            let sonarr_library_only = var("SEADEXER_SONARR_LIBRARY_ONLY")
+                .map(|v| v == "true")
+                .unwrap_or(false);
 
             Some(SonarrConfig {
                 url: sonarr_url,
                 api_key: sonarr_api_key,
                 timeout: sonarr_timeout,
+                insecure: sonarr_insecure,
+                api_key_in_query: sonarr_api_key_in_query,
+                library_only: sonarr_library_only,
             })
         } else {
             None
         };
 
-        let radarr_enabled = env::var("SEADEXER_RADARR_ENABLED")
+        let radarr_enabled = var("SEADEXER_RADARR_ENABLED")
             .map(|v| v != "false")
             .unwrap_or(true);
 
         let radarr = if radarr_enabled {
-            let raw_radarr_url = env::var("RADARR_BASE_URL")
-                .unwrap_or_else(|_| "http://localhost:7878".to_string());
+            let raw_radarr_url =
+                var("RADARR_BASE_URL").unwrap_or_else(|_| "http://localhost:7878".to_string());
             let radarr_url = parse_root_url(&raw_radarr_url, "RADARR_BASE_URL")?;
 
-            let radarr_api_key =
-                env::var("RADARR_API_KEY").context("Missing RADARR_API_KEY variable")?;
+            let radarr_api_key = var("RADARR_API_KEY").unwrap_or_default();
+
+            let radarr_timeout = Duration::from_secs(timeout_secs_from_env(
+                &var,
+                "RADARR_TIMEOUT_SECS",
+                timeout_secs,
+            ));
 
-            let radarr_timeout_secs = env::var("RADARR_TIMEOUT_SECS")
-                .ok()
-                .and_then(|value| value.parse::<u64>().ok())
-                .unwrap_or(timeout_secs);
-            let radarr_timeout = Duration::from_secs(radarr_timeout_secs.max(1));
+            let radarr_insecure = var("RADARR_INSECURE").map(|v| v == "true").unwrap_or(false);
+
+            let radarr_api_key_in_query = var("RADARR_APIKEY_IN_QUERY")
+                .map(|v| v == "true")
+                .unwrap_or(false);
 
             Some(RadarrConfig {
                 url: radarr_url,
                 api_key: radarr_api_key,
                 timeout: radarr_timeout,
+                insecure: radarr_insecure,
+                api_key_in_query: radarr_api_key_in_query,
             })
         } else {
             None
         };
 
-        if sonarr.is_none() && radarr.is_none() {
-            anyhow::bail!("At least one of Sonarr or Radarr must be enabled");
-        }
-
-        Ok(Self {
+        let config = Self {
             listen_addr,
+            dual_stack,
+            listen_uds,
+            admin_addr,
+            tls_cert_path,
+            tls_key_path,
             public_base_url,
+            trust_forwarded,
             releases_base_url,
             releases_timeout,
+            releases_connect_timeout,
+            releases_token,
+            trackers,
+            releases_expand_key,
+            nyaa_download_template,
             data_path,
-            mapping_source_url,
+            cache_path,
+            mapping_source_urls,
             mapping_refresh_interval,
+            mapping_refresh_jitter,
+            mapping_index_cache,
+            mapping_watch,
+            season_strategy,
+            absolute_numbering,
+            specials_in_packs,
+            min_pack_files,
             mapping_timeout,
             application_title,
             application_description,
+            fallback_title_template,
             default_limit,
+            max_limit,
+            rss_limit,
+            rss_max_age,
+            arr_concurrency,
+            search_limit,
+            outbound,
+            nyaa_seeders,
+            min_seeders,
+            best_only,
+            best_only_fallback,
+            best_as_freeleech,
+            empty_as_error,
+            skip_incomplete,
+            blocklist,
+            group_allowlist,
+            trusted_groups,
+            score_weights,
+            pubdate_fallback,
+            feed_etag,
+            comments_source,
+            guid_source,
+            title_cache_max,
+            prewarm,
             anilist_base_url,
             anilist_timeout,
+            unknown_format_as,
+            format_overrides,
+            tvmaze_base_url,
             sonarr,
             radarr,
-        })
+        };
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Checks the loaded configuration for problems, reporting every issue at once
+    /// instead of failing on the first one.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if self.sonarr.is_none() && self.radarr.is_none() {
+            problems.push("at least one of Sonarr or Radarr must be enabled".to_string());
+        }
+
+        if let Some(sonarr) = &self.sonarr {
+            if sonarr.api_key.trim().is_empty() {
+                problems.push("SONARR_API_KEY must be set when Sonarr is enabled".to_string());
+            }
+            check_http_url(&sonarr.url, "SONARR_BASE_URL", &mut problems);
+        }
+
+        if let Some(radarr) = &self.radarr {
+            if radarr.api_key.trim().is_empty() {
+                problems.push("RADARR_API_KEY must be set when Radarr is enabled".to_string());
+            }
+            check_http_url(&radarr.url, "RADARR_BASE_URL", &mut problems);
+        }
+
+        check_http_url(
+            &self.releases_base_url,
+            "SEADEXER_RELEASES_BASE_URL",
+            &mut problems,
+        );
+        if !self.nyaa_download_template.contains("{id}") {
+            problems
+                .push("SEADEXER_NYAA_DOWNLOAD_BASE must contain an {id} placeholder".to_string());
+        }
+        match Url::parse(&self.nyaa_download_template.replace("{id}", "1")) {
+            Ok(url) => check_http_url(&url, "SEADEXER_NYAA_DOWNLOAD_BASE", &mut problems),
+            Err(_) => problems.push("SEADEXER_NYAA_DOWNLOAD_BASE must be a valid URL".to_string()),
+        }
+        if self.mapping_source_urls.is_empty() {
+            problems.push("SEADEXER_MAPPING_SOURCE_URL must list at least one URL".to_string());
+        }
+        for url in &self.mapping_source_urls {
+            check_http_url(url, "SEADEXER_MAPPING_SOURCE_URL", &mut problems);
+        }
+        check_http_url(
+            &self.anilist_base_url,
+            "SEADEXER_ANILIST_BASE_URL",
+            &mut problems,
+        );
+        check_http_url(
+            &self.tvmaze_base_url,
+            "SEADEXER_TVMAZE_BASE_URL",
+            &mut problems,
+        );
+        if let Some(url) = &self.public_base_url {
+            check_http_url(url, "SEADEXER_PUBLIC_BASE_URL", &mut problems);
+        }
+
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(_), None) => {
+                problems.push("SEADEXER_TLS_KEY must be set with SEADEXER_TLS_CERT".to_string())
+            }
+            (None, Some(_)) => {
+                problems.push("SEADEXER_TLS_CERT must be set with SEADEXER_TLS_KEY".to_string())
+            }
+            (Some(_), Some(_)) if self.listen_uds.is_some() => problems.push(
+                "SEADEXER_TLS_CERT/SEADEXER_TLS_KEY cannot be combined with SEADEXER_LISTEN_UDS"
+                    .to_string(),
+            ),
+            _ => {}
+        }
+
+        if self.trackers.is_empty() {
+            problems.push("SEADEXER_TRACKERS must name at least one tracker".to_string());
+        }
+
+        if self.default_limit == 0 {
+            problems.push("SEADEXER_DEFAULT_LIMIT must be greater than zero".to_string());
+        }
+
+        if self.max_limit < self.default_limit {
+            problems.push("SEADEXER_MAX_LIMIT must be at least SEADEXER_DEFAULT_LIMIT".to_string());
+        }
+
+        if self.max_limit < self.rss_limit.max(self.search_limit) {
+            problems.push(
+                "SEADEXER_MAX_LIMIT must be at least SEADEXER_RSS_LIMIT and SEADEXER_SEARCH_LIMIT"
+                    .to_string(),
+            );
+        }
+
+        if !self.mapping_refresh_interval.is_zero()
+            && self.mapping_refresh_interval < MIN_MAPPING_REFRESH_INTERVAL
+        {
+            problems.push(format!(
+                "SEADEXER_MAPPING_REFRESH_SECS must be 0 or at least {} seconds",
+                MIN_MAPPING_REFRESH_INTERVAL.as_secs()
+            ));
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        anyhow::bail!("invalid configuration:\n  - {}", problems.join("\n  - "))
     }
 }
 
+/// Parses the `tv`/`movie` values accepted by the format settings.
+fn parse_format_kind(value: &str) -> Option<MediaFormat> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "tv" => Some(MediaFormat::Tv),
+        "movie" => Some(MediaFormat::Movie),
+        _ => None,
+    }
+}
+
+/// Reads a timeout in seconds, clamped to `[MIN_TIMEOUT_SECS, MAX_TIMEOUT_SECS]` so a
+/// typo can neither time out every request instantly nor let one hang for hours.
+fn timeout_secs_from_env(
+    var: &impl Fn(&str) -> Result<String, env::VarError>,
+    name: &str,
+    default: u64,
+) -> u64 {
+    let Some(secs) = var(name)
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+    else {
+        return default;
+    };
+
+    let clamped = secs.clamp(MIN_TIMEOUT_SECS, MAX_TIMEOUT_SECS);
+    if clamped != secs {
+        tracing::warn!(
+            variable = name,
+            configured = secs,
+            clamped,
+            "timeout outside of the supported range; clamping"
+        );
+    }
+    clamped
+}
+
+impl OutboundConfig {
+    /// Starts a `reqwest` client builder carrying the shared User-Agent and proxy settings.
+    /// Explicit proxies replace reqwest's default `HTTP_PROXY`/`HTTPS_PROXY` detection.
+    pub fn client_builder(&self, timeout: Duration) -> reqwest::Result<ClientBuilder> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(timeout)
+            .user_agent(&self.user_agent);
+
+        let no_proxy = self.no_proxy.as_deref().and_then(NoProxy::from_string);
+
+        if let Some(url) = &self.http_proxy {
+            builder = builder.proxy(Proxy::http(url.as_str())?.no_proxy(no_proxy.clone()));
+        }
+
+        if let Some(url) = &self.https_proxy {
+            builder = builder.proxy(Proxy::https(url.as_str())?.no_proxy(no_proxy));
+        }
+
+        Ok(builder)
+    }
+}
+
+fn check_http_url(url: &Url, label: &str, problems: &mut Vec<String>) {
+    if !matches!(url.scheme(), "http" | "https") {
+        problems.push(format!(
+            "{label} must use http or https, got `{}`",
+            url.scheme()
+        ));
+    } else if url.host_str().is_none_or(str::is_empty) {
+        problems.push(format!("{label} must include a host"));
+    }
+}
+
+/// Parses a base URL so that relative `join`s land underneath it. `Url::join` treats a
+/// final path segment without a trailing slash as a file and replaces it, which would turn
+/// `http://host/radarr` + `api/v3/...` into `http://host/api/v3/...`.
 fn parse_root_url(value: &str, label: &str) -> Result<Url> {
-    let mut normalized = value.trim().to_string();
-    if !normalized.ends_with('/') {
-        normalized.push('/');
+    let mut url =
+        Url::parse(value.trim()).with_context(|| format!("{label} must be a valid URL"))?;
+
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    url.set_query(None);
+    url.set_fragment(None);
+
+    Ok(url)
+}
+
+/// Points a PocketBase URL at its `/api/` root so collection paths join correctly.
+///
+/// `https://releases.moe`, `https://releases.moe/api` and
+/// `https://releases.moe/api/collections/entries/records` all become
+/// `https://releases.moe/api/`; a prefix such as `https://host/pb/` becomes `https://host/pb/api/`.
+fn normalize_api_root(mut url: Url) -> Url {
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|segments| segments.filter(|segment| !segment.is_empty()).collect())
+        .unwrap_or_default();
+
+    let prefix = match segments.iter().position(|segment| *segment == "api") {
+        Some(index) => &segments[..index],
+        None => &segments[..],
+    };
+
+    let mut path = String::from("/");
+    for segment in prefix {
+        path.push_str(segment);
+        path.push('/');
+    }
+    path.push_str("api/");
+    url.set_path(&path);
+
+    url
+}
+
+const REDACTED: &str = "***";
+
+/// Masks URL credentials and drops the query string, which may carry an API key.
+pub(crate) fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    if !url.username().is_empty() {
+        let _ = url.set_username(REDACTED);
+    }
+    if url.password().is_some() {
+        let _ = url.set_password(Some(REDACTED));
+    }
+    url.set_query(None);
+    url.to_string()
+}
+
+#[cfg(test)]
+impl AppConfig {
+    /// The defaults overridden by `vars`, with placeholder Sonarr and Radarr API keys so
+    /// validation passes.
+    pub fn for_tests(vars: &[(&str, &str)]) -> Self {
+        let vars: HashMap<&str, &str> = [("SONARR_API_KEY", "test"), ("RADARR_API_KEY", "test")]
+            .into_iter()
+            .chain(vars.iter().copied())
+            .collect();
+        Self::from_lookup(|name| {
+            vars.get(name)
+                .map(|value| value.to_string())
+                .ok_or(env::VarError::NotPresent)
+        })
+        .expect("test configuration is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joined(base: &str, path: &str) -> String {
+        parse_root_url(base, "TEST_URL")
+            .unwrap()
+            .join(path)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn root_urls_keep_their_path_prefix() {
+        let lookup = "api/v3/series/lookup";
+        assert_eq!(
+            joined("http://host/sonarr", lookup),
+            "http://host/sonarr/api/v3/series/lookup"
+        );
+        assert_eq!(
+            joined("http://host/sonarr/", lookup),
+            "http://host/sonarr/api/v3/series/lookup"
+        );
+        assert_eq!(
+            joined(" http://host:8989?x=1#top ", lookup),
+            "http://host:8989/api/v3/series/lookup"
+        );
+
+        let config = AppConfig::for_tests(&[("RADARR_BASE_URL", "http://host/radarr")]);
+        let radarr = config
+            .radarr
+            .unwrap()
+            .url
+            .join("api/v3/movie/lookup")
+            .unwrap();
+        assert_eq!(radarr.as_str(), "http://host/radarr/api/v3/movie/lookup");
+    }
+
+    #[test]
+    fn releases_base_urls_normalise_to_the_api_root() {
+        let records = |base: &str| {
+            normalize_api_root(parse_root_url(base, "TEST_URL").unwrap())
+                .join("collections/entries/records")
+                .unwrap()
+                .to_string()
+        };
+
+        for base in [
+            "https://releases.moe",
+            "https://releases.moe/",
+            "https://releases.moe/api",
+            "https://releases.moe/api/",
+            "https://releases.moe/api/collections/entries/records",
+        ] {
+            assert_eq!(
+                records(base),
+                "https://releases.moe/api/collections/entries/records",
+                "{base}"
+            );
+        }
+        assert_eq!(
+            records("https://host/pb"),
+            "https://host/pb/api/collections/entries/records"
+        );
     }
-    Url::parse(&normalized).with_context(|| format!("{label} must be a valid URL"))
 }
@@ -1,42 +1,260 @@
 use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
+    time::Duration,
 };
 
 use axum::{
     Json, Router,
+    body::{Body, Bytes},
     extract::{Query, State},
-    http::{StatusCode, header},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{get, post},
 };
+use futures_util::{StreamExt, future::try_join_all, stream};
 use serde::Deserialize;
 use serde_json::json;
 use thiserror::Error;
-use tracing::{debug, info};
+use time::OffsetDateTime;
+use tokio::sync::Semaphore;
+use tracing::{debug, info, warn};
 use url::Url;
 
-use crate::anilist::{AniListError, MediaFormat};
+use crate::anilist::{self, AniListError, AniListMedia, MediaFormat};
+use crate::config::{AppConfig, redact_url};
 use crate::radarr::RadarrError;
-use crate::releases::{ReleasesError, Torrent};
-use crate::torznab::{self, ChannelMetadata, TorznabItem};
+use crate::release_filter::SpecialsInPacks;
+use crate::releases::{
+    ReleasesError, Torrent, extract_nyaa_id, score_torrent, share_entry_anilist_ids,
+};
+use crate::torznab::{
+    self, AttrSelection, ChannelMetadata, CommentsSource, GuidSource, SearchingCaps,
+    TorznabCategory, TorznabItem, TorznabItemBuilder,
+};
+use crate::tvmaze::ExternalShowId;
 use crate::{
     AppState, SharedAppState,
-    mapping::{MappingError, TvdbMapping, parse_season_key},
+    mapping::{MappingError, SeasonStrategy, TvdbMapping, parse_season_key},
     sonarr::SonarrError,
 };
 
+/// Upper bound on the seasons expanded from a single `season=a-b` range.
+const MAX_SEASON_RANGE: u32 = 50;
+
+/// Nyaa pages scraped at once for peer counts.
+const NYAA_SCRAPE_CONCURRENCY: usize = 4;
+
+/// Feeds with at least this many items are streamed instead of rendered into one buffer.
+const STREAM_FEED_MIN_ITEMS: usize = 100;
+
 pub fn router(state: SharedAppState) -> Router {
     Router::new()
+        .route("/", get(info))
         .route("/health", get(health))
         .route("/api", get(torznab_handler))
+        .route("/api/caps", get(caps_handler))
+        .route("/definition", get(definition))
+        .with_state(state)
+}
+
+/// Operational routes, served only on `SEADEXER_ADMIN_ADDR` since they take no credentials.
+pub fn admin_router(state: SharedAppState) -> Router {
+    Router::new()
+        .route("/mappings/stats", get(mapping_stats))
+        .route("/mappings/refresh", post(refresh_mappings))
+        .route("/metrics", get(metrics))
+        .route("/debug/config", get(debug_config))
+        .route("/debug/resolve", get(debug_resolve))
+        .route("/debug/caps", get(debug_caps))
         .with_state(state)
 }
 
+/// Everything Prowlarr asks for when adding a generic Torznab indexer, plus the category
+/// ids to pick in Sonarr/Radarr, so nobody has to copy them out of the caps by hand.
+async fn definition(
+    State(state): State<SharedAppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, HttpError> {
+    let metadata = build_channel_metadata(&state, &headers)?;
+    let torznab_url = torznab_url(&metadata)?;
+
+    let categories: Vec<_> = metadata
+        .categories
+        .iter()
+        .map(|category| {
+            json!({
+                "id": category.id,
+                "name": category.name,
+                "subcategories": category
+                    .subcategories
+                    .iter()
+                    .map(|sub| json!({ "id": sub.id, "name": sub.name }))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "name": metadata.title,
+        "description": metadata.description,
+        "implementation": "Torznab",
+        "protocol": "torrent",
+        "base_url": metadata.site_link,
+        "api_path": torznab_url.path(),
+        "torznab_url": torznab_url.as_str(),
+        "limits": { "default": metadata.default_limit, "max": metadata.max_limit },
+        "categories": categories,
+        "searching": {
+            "search": metadata.searching.search,
+            "tv-search": metadata.searching.tv_search,
+            "movie-search": metadata.searching.movie_search,
+        },
+        "sonarr_categories": state.sonarr.is_some().then(tv_category_ids),
+        "radarr_categories": state.radarr.is_some().then(movie_category_ids),
+        "caps": torznab::render_caps(&metadata)?,
+    })))
+}
+
 async fn health() -> impl IntoResponse {
     Json(json!({ "status": "ok" }))
 }
 
+/// Landing page for people opening the base URL in a browser: what is configured and
+/// which URL to give Prowlarr.
+async fn info(
+    State(state): State<SharedAppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, HttpError> {
+    let metadata = build_channel_metadata(&state, &headers)?;
+    let torznab_url = torznab_url(&metadata)?;
+
+    let mut operations = vec!["caps", "search"];
+    if state.sonarr.is_some() {
+        operations.push("tvsearch");
+    }
+    if state.radarr.is_some() {
+        operations.push("movie-search");
+    }
+
+    Ok(Json(json!({
+        "name": metadata.title,
+        "version": env!("CARGO_PKG_VERSION"),
+        "torznab_url": torznab_url.as_str(),
+        "operations": operations,
+        "upstreams": {
+            "releases": redact_url(&state.config.releases_base_url),
+            "anilist": redact_url(&state.config.anilist_base_url),
+            "mappings": state
+                .config
+                .mapping_source_urls
+                .iter()
+                .map(redact_url)
+                .collect::<Vec<_>>(),
+            "sonarr": state.config.sonarr.as_ref().map(|sonarr| redact_url(&sonarr.url)),
+            "radarr": state.config.radarr.as_ref().map(|radarr| redact_url(&radarr.url)),
+        },
+    })))
+}
+
+async fn mapping_stats(State(state): State<SharedAppState>) -> Response {
+    match state.mappings.stats().await {
+        Some(stats) => Json(stats).into_response(),
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "error": "mappings have not been loaded yet" })),
+        )
+            .into_response(),
+    }
+}
+
+/// Prometheus text exposition of mapping freshness, for alerting on stalled refreshes.
+async fn metrics(State(state): State<SharedAppState>) -> impl IntoResponse {
+    let mut body = String::new();
+
+    body.push_str("# HELP seadexerr_mappings_age_seconds Age of the loaded mappings file.\n");
+    body.push_str("# TYPE seadexerr_mappings_age_seconds gauge\n");
+    if let Some(age) = state.mappings.age().await {
+        body.push_str(&format!(
+            "seadexerr_mappings_age_seconds {}\n",
+            age.as_secs()
+        ));
+    }
+
+    body.push_str(
+        "# HELP seadexerr_mapping_refresh_failures_total Failed scheduled mapping refreshes.\n",
+    );
+    body.push_str("# TYPE seadexerr_mapping_refresh_failures_total counter\n");
+    body.push_str(&format!(
+        "seadexerr_mapping_refresh_failures_total {}\n",
+        state.mappings.failed_refreshes()
+    ));
+
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        body,
+    )
+}
+
+async fn debug_config(State(state): State<SharedAppState>) -> impl IntoResponse {
+    Json(state.config.redacted())
+}
+
+#[derive(Debug, Deserialize)]
+struct ResolveQuery {
+    tvdbid: i64,
+    season: Option<u32>,
+    tmdbid: Option<i64>,
+    anilistid: Option<i64>,
+}
+
+/// Explains how a tvdb id resolves against the mappings, and where the show is filed if it
+/// doesn't. Doesn't touch the negative cache used by real searches.
+async fn debug_resolve(
+    State(state): State<SharedAppState>,
+    Query(query): Query<ResolveQuery>,
+) -> Result<impl IntoResponse, HttpError> {
+    let nearby = state
+        .mappings
+        .resolve_nearby(query.tvdbid, query.season, query.tmdbid, query.anilistid)
+        .await?;
+    Ok(Json(nearby))
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct CapsDebugQuery {
+    validate: bool,
+}
+
+/// Serves the caps document, or with `validate=true` a list of problems in it that
+/// Prowlarr would otherwise reject with an opaque error.
+async fn debug_caps(
+    State(state): State<SharedAppState>,
+    Query(query): Query<CapsDebugQuery>,
+) -> Result<Response, HttpError> {
+    if !query.validate {
+        return respond_caps(&state);
+    }
+
+    let metadata = build_channel_metadata(&state, &HeaderMap::new())?;
+    let warnings = torznab::validate_caps(&torznab::render_caps(&metadata)?);
+    Ok(Json(json!({ "valid": warnings.is_empty(), "warnings": warnings })).into_response())
+}
+
+async fn refresh_mappings(
+    State(state): State<SharedAppState>,
+) -> Result<impl IntoResponse, HttpError> {
+    info!("manual mapping refresh requested");
+    state.mappings.refresh().await?;
+    Ok(Json(
+        json!({ "status": "refreshed", "mappings": state.mappings.stats().await }),
+    ))
+}
+
 #[derive(Debug, Clone, Deserialize, Default)]
 #[serde(default)]
 struct TorznabQuery {
@@ -45,20 +263,41 @@ struct TorznabQuery {
     cat: Option<String>,
     limit: Option<usize>,
     offset: Option<usize>,
-    #[allow(dead_code)]
     imdbid: Option<String>,
     season: Option<String>,
+    /// Episode number, only used as an absolute episode with `SEADEXER_ABSOLUTE_NUMBERING`.
+    ep: Option<String>,
     #[serde(rename = "tvdbid")]
     tvdb_id: Option<String>,
+    #[serde(rename = "tvmazeid")]
+    tvmaze_id: Option<String>,
+    /// TVRage id.
+    rid: Option<String>,
     #[serde(rename = "tmdbid")]
     tmdb_id: Option<String>,
+    #[serde(rename = "anilistid")]
+    anilist_id: Option<String>,
     #[serde(rename = "q")]
     query: Option<String>,
+    /// Smallest acceptable release size in bytes.
+    minsize: Option<u64>,
+    /// Largest acceptable release size in bytes.
+    maxsize: Option<u64>,
+    /// Comma-separated extended attrs to include, or `all`; only core attrs when unset.
+    attrs: Option<String>,
+    /// `extended=1` asks for every extended attr.
+    extended: Option<String>,
+    /// Response format. Only `xml`, the default, is served.
+    #[serde(rename = "o")]
+    output: Option<String>,
 }
 
 impl TorznabQuery {
     fn operation(&self) -> TorznabOperation<'_> {
-        match self.operation.as_deref().unwrap_or("tvsearch") {
+        // a bare `/api` is an indexer probing capabilities; keep `tvsearch` as the default
+        // when search parameters are present without `t`
+        let default = if self.is_empty() { "caps" } else { "tvsearch" };
+        match self.operation.as_deref().unwrap_or(default) {
             "caps" => TorznabOperation::Caps,
             "search" => TorznabOperation::Search,
             "tvsearch" | "tv-search" => TorznabOperation::TvSearch,
@@ -67,25 +306,116 @@ impl TorznabQuery {
         }
     }
 
+    fn is_empty(&self) -> bool {
+        self.operation.is_none()
+            && self.cat.is_none()
+            && self.limit.is_none()
+            && self.offset.is_none()
+            && self.imdbid.is_none()
+            && self.season.is_none()
+            && self.ep.is_none()
+            && self.tvdb_id.is_none()
+            && self.tvmaze_id.is_none()
+            && self.rid.is_none()
+            && self.tmdb_id.is_none()
+            && self.anilist_id.is_none()
+            && self.query.is_none()
+            && self.minsize.is_none()
+            && self.maxsize.is_none()
+            && self.attrs.is_none()
+            && self.extended.is_none()
+    }
+
+    fn attr_selection(&self) -> AttrSelection {
+        AttrSelection::from_params(self.attrs.as_deref(), self.extended.as_deref())
+    }
+
+    /// Requested page size, defaulting to the operation's `default` and clamped to
+    /// `[1, SEADEXER_MAX_LIMIT]`.
+    fn page_limit(&self, default: usize, config: &AppConfig) -> usize {
+        self.limit.unwrap_or(default).clamp(1, config.max_limit)
+    }
+
+    fn size_allowed(&self, size_bytes: u64) -> bool {
+        self.minsize.is_none_or(|min| size_bytes >= min)
+            && self.maxsize.is_none_or(|max| max == 0 || size_bytes <= max)
+    }
+
     fn tvdb_identifier(&self) -> Option<i64> {
         self.tvdb_id
             .as_deref()
             .and_then(|value| value.trim().parse::<i64>().ok())
     }
 
+    /// A TVmaze or TVRage id to translate into a tvdb id when `tvdbid` is absent.
+    fn external_show_identifier(&self) -> Option<ExternalShowId> {
+        let parse = |value: &Option<String>| value.as_deref()?.trim().parse::<i64>().ok();
+        parse(&self.tvmaze_id)
+            .map(ExternalShowId::TvMaze)
+            .or_else(|| parse(&self.rid).map(ExternalShowId::TvRage))
+    }
+
+    fn anilist_identifier(&self) -> Option<i64> {
+        self.anilist_id
+            .as_deref()
+            .and_then(|value| value.trim().parse::<i64>().ok())
+    }
+
     fn tmdb_identifier(&self) -> Option<i64> {
         self.tmdb_id
             .as_deref()
             .and_then(|value| value.trim().parse::<i64>().ok())
     }
 
-    fn season_number(&self) -> Option<u32> {
-        self.season
-            .as_deref()
-            .and_then(|value| value.trim().parse::<u32>().ok())
+    /// The `ep` param as an absolute episode, when `SEADEXER_ABSOLUTE_NUMBERING` is on.
+    fn absolute_episode(&self, config: &AppConfig) -> Option<u32> {
+        if !config.absolute_numbering {
+            return None;
+        }
+        self.ep
+            .as_deref()?
+            .trim()
+            .parse()
+            .ok()
+            .filter(|episode| *episode > 0)
+    }
+
+    /// Parses `season` as a single number, a range (`1-3`) or a comma list (`1,3-4`).
+    fn season_numbers(&self) -> Vec<u32> {
+        let mut seasons = Vec::new();
+        let Some(value) = self.season.as_deref() else {
+            return seasons;
+        };
+
+        for part in value.split(',').map(str::trim) {
+            if let Some((start, end)) = part.split_once('-') {
+                let (Some(start), Some(end)) = (parse_season_param(start), parse_season_param(end))
+                else {
+                    continue;
+                };
+                for season in start..=end.min(start.saturating_add(MAX_SEASON_RANGE)) {
+                    if !seasons.contains(&season) {
+                        seasons.push(season);
+                    }
+                }
+            } else if let Some(season) = parse_season_param(part)
+                && !seasons.contains(&season)
+            {
+                seasons.push(season);
+            }
+        }
+
+        seasons
     }
 }
 
+/// Parses one season from the `season` param. Some clients send `S01` rather than `1`, so
+/// an `s`/`S` prefix is accepted; leading zeros are already fine for `parse`.
+fn parse_season_param(value: &str) -> Option<u32> {
+    let value = value.trim();
+    value.strip_prefix(['s', 'S']).unwrap_or(value).parse().ok()
+}
+
 enum TorznabOperation<'a> {
     Caps,
     Search,
@@ -94,6 +424,27 @@ enum TorznabOperation<'a> {
     Unsupported(&'a str),
 }
 
+/// Looks up AniList media, applying `SEADEXER_FORMAT_OVERRIDES` so overridden entries
+/// are routed as TV or movie regardless of their AniList format.
+async fn fetch_media(
+    state: &AppState,
+    anilist_ids: &[i64],
+) -> Result<HashMap<i64, AniListMedia>, HttpError> {
+    let mut media_lookup = state
+        .anilist
+        .fetch_media(anilist_ids)
+        .await
+        .map_err(HttpError::AniList)?;
+
+    for (anilist_id, media) in media_lookup.iter_mut() {
+        if let Some(format) = state.config.format_overrides.get(anilist_id) {
+            media.format = format.clone();
+        }
+    }
+
+    Ok(media_lookup)
+}
+
 fn format_allowed(format: &MediaFormat) -> bool {
     matches!(
         format,
@@ -105,9 +456,14 @@ fn movie_format_allowed(format: &MediaFormat) -> bool {
     matches!(format, MediaFormat::Movie)
 }
 
+async fn caps_handler(State(state): State<SharedAppState>) -> Result<Response, HttpError> {
+    respond_caps(&state)
+}
+
 async fn torznab_handler(
     State(state): State<SharedAppState>,
-    Query(query): Query<TorznabQuery>,
+    Query(mut query): Query<TorznabQuery>,
+    headers: HeaderMap,
 ) -> Result<Response, HttpError> {
     let operation = query.operation();
     let operation_name = match &operation {
@@ -118,11 +474,28 @@ async fn torznab_handler(
         TorznabOperation::Unsupported(name) => name,
     };
 
+    if let Some(output) = query.output.as_deref()
+        && !output.trim().eq_ignore_ascii_case("xml")
+    {
+        debug!(
+            output,
+            "unsupported output format requested; answering with XML"
+        );
+    }
+
+    let anilist_id = query.anilist_identifier();
     let valid = match &operation {
         TorznabOperation::Caps => true,
+        TorznabOperation::Search | TorznabOperation::TvSearch | TorznabOperation::MovieSearch
+            if anilist_id.is_some() =>
+        {
+            category_filter_matches(&query.cat)
+        }
         TorznabOperation::Search => query.query.is_none() && category_filter_matches(&query.cat),
         TorznabOperation::TvSearch => {
-            query.tvdb_identifier().is_some() && query.season_number().is_some()
+            (query.tvdb_identifier().is_some() || query.external_show_identifier().is_some())
+                && (!query.season_numbers().is_empty()
+                    || query.absolute_episode(&state.config).is_some())
         }
         TorznabOperation::MovieSearch => query.tmdb_identifier().is_some(),
         TorznabOperation::Unsupported(_) => false,
@@ -133,6 +506,7 @@ async fn torznab_handler(
             operation = operation_name,
             tvdb = query.tvdb_id.as_deref(),
             tmdb = query.tmdb_id.as_deref(),
+            anilist = query.anilist_id.as_deref(),
             season = query.season.as_deref(),
             limit = query.limit,
             "Valid torznab request received"
@@ -142,26 +516,87 @@ async fn torznab_handler(
             operation = operation_name,
             tvdb = query.tvdb_id.as_deref(),
             tmdb = query.tmdb_id.as_deref(),
+            anilist = query.anilist_id.as_deref(),
             season = query.season.as_deref(),
             limit = query.limit,
             "Invalid torznab request received"
         );
     }
 
+    if let Some(anilist_id) = anilist_id
+        && matches!(
+            operation,
+            TorznabOperation::Search | TorznabOperation::TvSearch | TorznabOperation::MovieSearch
+        )
+    {
+        return respond_anilist_search(&state, &query, &headers, anilist_id).await;
+    }
+
     match operation {
         TorznabOperation::Caps => respond_caps(&state),
-        TorznabOperation::Search => respond_generic_search(&state, &query).await,
-        TorznabOperation::TvSearch => respond_tv_search(&state, &query).await,
-        TorznabOperation::MovieSearch => respond_movie_search(&state, &query).await,
+        TorznabOperation::Search => respond_generic_search(&state, &query, &headers).await,
+        TorznabOperation::TvSearch => {
+            if query.tvdb_identifier().is_none()
+                && let Some(external_id) = query.external_show_identifier()
+            {
+                match resolve_external_show(&state, external_id).await {
+                    Some(tvdb_id) => query.tvdb_id = Some(tvdb_id.to_string()),
+                    None => return respond_empty_feed(&state, &query, &headers),
+                }
+            }
+            respond_tv_search(&state, &query, &headers).await
+        }
+        TorznabOperation::MovieSearch => respond_movie_search(&state, &query, &headers).await,
         TorznabOperation::Unsupported(name) => {
             Err(HttpError::UnsupportedOperation(name.to_string()))
         }
     }
 }
 
+/// Translates a `tvmazeid`/`rid` into a tvdb id. Lookup failures are logged and treated
+/// as unresolvable so the client gets an empty feed rather than an error.
+async fn resolve_external_show(state: &AppState, external_id: ExternalShowId) -> Option<i64> {
+    match state.tvmaze.resolve_tvdb_id(external_id).await {
+        Ok(Some(tvdb_id)) => {
+            debug!(id = ?external_id, tvdb_id, "resolved tvdb id via TVmaze");
+            Some(tvdb_id)
+        }
+        Ok(None) => {
+            info!(id = ?external_id, "no tvdb id known for show; returning empty feed");
+            None
+        }
+        Err(error) => {
+            warn!(id = ?external_id, error = %error, "TVmaze lookup failed; returning empty feed");
+            None
+        }
+    }
+}
+
+fn respond_empty_feed(
+    state: &AppState,
+    query: &TorznabQuery,
+    headers: &HeaderMap,
+) -> Result<Response, HttpError> {
+    let metadata = build_channel_metadata(state, headers)?;
+    let xml = torznab::render_feed(&metadata, &[], query.offset.unwrap_or(0), 0)?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+        xml,
+    )
+        .into_response())
+}
+
 fn respond_caps(state: &AppState) -> Result<Response, HttpError> {
-    let metadata = build_channel_metadata(state)?;
-    let xml = torznab::render_caps(&metadata)?;
+    // caps only depend on configuration, which is fixed for the life of the process
+    let xml = match state.caps_xml.get() {
+        Some(xml) => xml.clone(),
+        None => {
+            // caps carry no links, so the request's forwarded headers don't matter
+            let metadata = build_channel_metadata(state, &HeaderMap::new())?;
+            let xml = Bytes::from(torznab::render_caps(&metadata)?);
+            state.caps_xml.get_or_init(|| xml).clone()
+        }
+    };
     Ok((
         [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
         xml,
@@ -172,13 +607,10 @@ fn respond_caps(state: &AppState) -> Result<Response, HttpError> {
 async fn respond_generic_search(
     state: &AppState,
     query: &TorznabQuery,
+    headers: &HeaderMap,
 ) -> Result<Response, HttpError> {
-    let metadata = build_channel_metadata(state)?;
-    let limit = query
-        .limit
-        .unwrap_or(state.config.default_limit)
-        .max(1)
-        .min(state.config.default_limit);
+    let metadata = build_channel_metadata(state, headers)?;
+    let limit = query.page_limit(state.config.rss_limit, &state.config);
     let offset = query.offset.unwrap_or(0);
 
     if query.query.is_some() {
@@ -225,13 +657,33 @@ async fn respond_generic_search(
         offset, "serving torznab search via recent public torrents"
     );
 
-    let fetch_limit = state.config.default_limit;
+    let fetch_limit = state.config.max_limit;
     let mut torrents = state
         .releases
         .recent_public_torrents(fetch_limit)
         .await
         .map_err(HttpError::Releases)?;
 
+    // releases without a publish date are kept; there's nothing to age them by
+    // a window reaching back past the earliest representable date cuts nothing
+    if let Some(max_age) = state.config.rss_max_age
+        && let Some(cutoff) = time::Duration::try_from(max_age)
+            .ok()
+            .and_then(|max_age| OffsetDateTime::now_utc().checked_sub(max_age))
+    {
+        let before = torrents.len();
+        torrents.retain(|torrent| {
+            torrent
+                .published
+                .is_none_or(|published| published >= cutoff)
+        });
+        debug!(
+            dropped = before - torrents.len(),
+            max_age_hours = max_age.as_secs() / 3600,
+            "dropped releases older than the rss window"
+        );
+    }
+
     if torrents.is_empty() {
         let xml = torznab::render_feed(&metadata, &[], offset, 0)?;
         return Ok((
@@ -241,21 +693,47 @@ async fn respond_generic_search(
             .into_response());
     }
 
+    share_entry_anilist_ids(&mut torrents);
+
     let missing_ids: Vec<String> = torrents
         .iter()
         .filter(|torrent| torrent.anilist_id.is_none())
         .map(|torrent| torrent.id.clone())
         .collect();
-
-    let resolved_anilist = if missing_ids.is_empty() {
-        HashMap::new()
-    } else {
-        state
-            .releases
-            .resolve_anilist_ids_for_torrents(&missing_ids)
-            .await
-            .map_err(HttpError::Releases)?
-    };
+    let mut known_ids: Vec<i64> = torrents
+        .iter()
+        .filter_map(|torrent| torrent.anilist_id)
+        .collect();
+    known_ids.sort_unstable();
+    known_ids.dedup();
+
+    // Media for the ids releases.moe already gave us doesn't depend on the resolve
+    // round-trip, so both requests go out together; only newly resolved ids wait.
+    let (resolved_anilist, media_lookup) = tokio::join!(
+        async {
+            if missing_ids.is_empty() {
+                return Ok(HashMap::new());
+            }
+            state
+                .releases
+                .resolve_anilist_ids_for_torrents(&missing_ids)
+                .await
+        },
+        fetch_media(state, &known_ids),
+    );
+    let resolved_anilist = resolved_anilist.map_err(HttpError::Releases)?;
+    let mut media_lookup = media_lookup?;
+
+    let late_ids: Vec<i64> = resolved_anilist
+        .values()
+        .copied()
+        .filter(|anilist_id| !media_lookup.contains_key(anilist_id))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    if !late_ids.is_empty() {
+        media_lookup.extend(fetch_media(state, &late_ids).await?);
+    }
 
     torrents = torrents
         .into_iter()
@@ -269,42 +747,43 @@ async fn respond_generic_search(
         })
         .collect();
 
-    let anilist_ids: Vec<i64> = torrents
-        .iter()
-        .filter_map(|torrent| torrent.anilist_id)
-        .collect();
-
-    let media_lookup = state
-        .anilist
-        .fetch_media(&anilist_ids)
-        .await
-        .map_err(HttpError::AniList)?;
+    let mut by_anilist: HashMap<i64, Vec<Torrent>> = HashMap::new();
+    for torrent in torrents {
+        if let Some(anilist_id) = torrent.anilist_id {
+            by_anilist.entry(anilist_id).or_default().push(torrent);
+        }
+    }
 
     let mut eligible: Vec<Torrent> = Vec::new();
 
-    for torrent in torrents.into_iter() {
-        let Some(anilist_id) = torrent.anilist_id else {
-            continue;
-        };
-
+    for (anilist_id, group) in by_anilist {
         let Some(media) = media_lookup.get(&anilist_id) else {
             continue;
         };
 
-        let include = match &media.format {
-            MediaFormat::Movie => true,
-            format if format_allowed(format) => torrent.files.len() > 1,
-            _ => false,
-        };
-
-        if include {
-            eligible.push(torrent);
+        let is_movie = media.format == MediaFormat::Movie;
+        if !is_movie && !format_allowed(&media.format) {
+            continue;
         }
+
+        eligible.extend(group.into_iter().filter(|torrent| {
+            query.size_allowed(torrent.size_bytes)
+                && state.release_filter.allows(torrent)
+                && (is_movie || season_pack_allowed(state, torrent))
+        }));
     }
 
+    let mut eligible = apply_best_only(state, eligible);
+    // newest first with the id as a tie-breaker, so offsets page consistently across polls
+    eligible.sort_by(|a, b| b.published.cmp(&a.published).then_with(|| a.id.cmp(&b.id)));
     let total = eligible.len();
 
+    // Titles for every eligible entry stay cached, not just those on the requested page.
+    let (active_tvdb_ids, active_tmdb_ids) =
+        collect_active_ids(state, &eligible, &media_lookup).await?;
+
     let window: Vec<Torrent> = eligible.into_iter().skip(offset).take(limit).collect();
+    let window = apply_nyaa_peers(state, window).await;
 
     if window.is_empty() {
         let xml = torznab::render_feed(&metadata, &[], offset, total)?;
@@ -315,12 +794,9 @@ async fn respond_generic_search(
             .into_response());
     }
 
-    let mut tv_title_cache: HashMap<(i64, u32), String> = HashMap::new();
-    let mut movie_title_cache: HashMap<i64, String> = HashMap::new();
-    let mut active_tvdb_ids: HashSet<i64> = HashSet::new();
-    let mut active_tmdb_ids: HashSet<i64> = HashSet::new();
-    let mut items = Vec::with_capacity(window.len());
-
+    // Work out what each item needs first, so every distinct title is looked up once and
+    // the lookups can run side by side.
+    let mut planned = Vec::with_capacity(window.len());
     for torrent in window.into_iter() {
         let Some(anilist_id) = torrent.anilist_id else {
             debug!(torrent_id = %torrent.id, "skipping torrent without AniList id");
@@ -335,37 +811,42 @@ async fn respond_generic_search(
             continue;
         };
 
-        match &media.format {
+        let source = match &media.format {
+            // without the matching *arr, items are still listed under their AniList title
+            // so single-app setups get a mixed feed
             format if format_allowed(format) => {
-                if state.sonarr.is_some() {
-                    let title = resolve_tv_generic_title(
-                        state,
-                        &torrent,
-                        &mut tv_title_cache,
-                        &mut active_tvdb_ids,
-                    )
-                    .await?;
-                    items.push(build_torznab_item(torrent, title, tv_category_ids()));
+                if state.sonarr.is_none() {
+                    TitleSource::Fallback
+                } else {
+                    if !in_sonarr_library(state, anilist_id).await? {
+                        debug!(
+                            anilist_id,
+                            "skipping torrent for series not in Sonarr library"
+                        );
+                        continue;
+                    }
+                    let mappings = state
+                        .mappings
+                        .resolve_tvdb_mappings(anilist_id)
+                        .await
+                        .map_err(HttpError::Mapping)?;
+                    TitleSource::Series(select_tvdb_and_season(
+                        &mappings,
+                        state.config.season_strategy,
+                    ))
                 }
             }
             MediaFormat::Movie => {
-                if state.radarr.is_some() {
-                    match resolve_movie_generic_title(
-                        state,
-                        anilist_id,
-                        &mut movie_title_cache,
-                        &mut active_tmdb_ids,
+                if state.radarr.is_none() {
+                    TitleSource::Fallback
+                } else {
+                    TitleSource::Movie(
+                        state
+                            .mappings
+                            .resolve_tmdb_id(anilist_id)
+                            .await
+                            .map_err(HttpError::Mapping)?,
                     )
-                    .await?
-                    {
-                        Some(title) => {
-                            items.push(build_torznab_item(torrent, title, movie_category_ids()));
-                        }
-                        None => {
-                            let fallback = default_torrent_title(&torrent.id);
-                            items.push(build_torznab_item(torrent, fallback, movie_category_ids()));
-                        }
-                    }
                 }
             }
             other => {
@@ -374,11 +855,65 @@ async fn respond_generic_search(
                     format = ?other,
                     "skipping torrent due to unsupported AniList format"
                 );
+                continue;
             }
-        }
+        };
+        planned.push((torrent, media, source));
     }
 
-    let xml = torznab::render_feed(&metadata, &items, offset, total)?;
+    let (series_titles, movie_titles) = resolve_arr_titles(state, &planned).await?;
+
+    let mut items = Vec::with_capacity(planned.len());
+    for (torrent, media, source) in planned {
+        let genres = media.genres.clone();
+        match source {
+            TitleSource::Fallback => {
+                let categories = if media.format == MediaFormat::Movie {
+                    movie_category_ids()
+                } else {
+                    tv_category_ids()
+                };
+                let title = fallback_title(state, &torrent, Some(media));
+                items.push(
+                    torznab_item(state, torrent, title, categories)
+                        .genres(genres)
+                        .build(),
+                );
+            }
+            TitleSource::Series(seasons) => {
+                let mut titles = Vec::new();
+                for (tvdb_id, season) in seasons {
+                    let Some(Some(series_title)) = series_titles.get(&tvdb_id) else {
+                        continue;
+                    };
+                    let title = format_series_feed_title(series_title, season);
+                    if !titles.contains(&title) {
+                        titles.push(title);
+                    }
+                }
+                if titles.is_empty() {
+                    titles.push(fallback_title(state, &torrent, Some(media)));
+                }
+                // with `SeasonStrategy::All` a release is listed once per mapped season;
+                // extra copies get a distinct guid so clients don't collapse them
+                for (index, title) in titles.into_iter().enumerate() {
+                    let mut item = torznab_item(state, torrent.clone(), title, tv_category_ids());
+                    if index > 0 {
+                        item = item.guid(format!("{}#{index}", torrent_guid(state, &torrent)));
+                    }
+                    items.push(item.genres(genres.clone()).build());
+                }
+            }
+            TitleSource::Movie(tmdb_id) => {
+                let title = match tmdb_id.and_then(|tmdb_id| movie_titles.get(&tmdb_id)) {
+                    Some(Some(title)) => title.clone(),
+                    _ => fallback_title(state, &torrent, Some(media)),
+                };
+                let item = torznab_item(state, torrent, title, movie_category_ids());
+                items.push(item.genres(genres).build());
+            }
+        }
+    }
 
     if let Some(sonarr) = &state.sonarr {
         sonarr
@@ -394,20 +929,58 @@ async fn respond_generic_search(
             .map_err(HttpError::Radarr)?;
     }
 
+    let items = query.attr_selection().apply(items);
+
+    if !state.config.feed_etag {
+        let xml = feed_body(metadata, items, offset, total)?;
+        return Ok((
+            [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+            xml,
+        )
+            .into_response());
+    }
+
+    let etag = torznab::feed_etag(&items, offset, total);
+    let etag_value = HeaderValue::from_str(&etag).expect("hex etag is a valid header value");
+    if etag_matches(headers, &etag) {
+        debug!(etag = %etag, "generic feed unchanged; returning 304");
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag_value)]).into_response());
+    }
+
+    let xml = feed_body(metadata, items, offset, total)?;
     Ok((
-        [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/rss+xml; charset=utf-8"),
+            ),
+            (header::ETAG, etag_value),
+        ],
         xml,
     )
         .into_response())
 }
 
-async fn respond_tv_search(state: &AppState, query: &TorznabQuery) -> Result<Response, HttpError> {
-    let metadata = build_channel_metadata(state)?;
-    let limit = query
-        .limit
-        .unwrap_or(state.config.default_limit)
-        .max(1)
-        .min(state.config.default_limit);
+/// Whether the request's `If-None-Match` names `etag`. Weak comparison, as the feed
+/// ETags are weak.
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let strip = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = strip(etag);
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|candidate| candidate.trim() == "*" || strip(candidate) == etag)
+}
+
+async fn respond_tv_search(
+    state: &AppState,
+    query: &TorznabQuery,
+    headers: &HeaderMap,
+) -> Result<Response, HttpError> {
+    let metadata = build_channel_metadata(state, headers)?;
+    let limit = query.page_limit(state.config.search_limit, &state.config);
 
     let offset = query.offset.unwrap_or(0);
 
@@ -437,76 +1010,69 @@ async fn respond_tv_search(state: &AppState, query: &TorznabQuery) -> Result<Res
         }
     };
 
-    let season = match query.season_number() {
-        Some(value) => value,
-        None => {
-            debug!(
-                tvdb_id,
-                limit, "tvsearch missing season; returning empty feed without error"
-            );
-            let xml = torznab::render_feed(&metadata, &[], offset, 0)?;
-            return Ok((
-                [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
-                xml,
-            )
-                .into_response());
-        }
+    // an absolute episode already pins down the season, so `season` is ignored alongside it
+    let absolute_episode = query.absolute_episode(&state.config);
+    let seasons = match absolute_episode {
+        Some(_) => Vec::new(),
+        None => query.season_numbers(),
     };
+    if seasons.is_empty() && absolute_episode.is_none() {
+        debug!(
+            tvdb_id,
+            limit, "tvsearch missing season; returning empty feed without error"
+        );
+        let xml = torznab::render_feed(&metadata, &[], offset, 0)?;
+        return Ok((
+            [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+            xml,
+        )
+            .into_response());
+    }
 
-    debug!(tvdb_id, season, limit, "resolving plexanibridge mapping");
+    let mut targets: Vec<(u32, i64)> = Vec::new();
+    if let Some(episode) = absolute_episode {
+        debug!(
+            tvdb_id,
+            episode, limit, "resolving absolute episode through mapping ranges"
+        );
 
-    let anilist_id = match state
-        .mappings
-        .resolve_anilist_id(tvdb_id, season)
-        .await
-        .map_err(HttpError::Mapping)?
-    {
-        Some(id) => id,
-        None => {
-            info!(
+        match state
+            .mappings
+            .resolve_by_absolute_episode(tvdb_id, episode)
+            .await
+            .map_err(HttpError::Mapping)?
+        {
+            Some((anilist_id, season)) => targets.push((season, anilist_id)),
+            None => info!(
                 tvdb_id,
-                season, "no anilist mapping found; returning empty result set"
-            );
-            let xml = torznab::render_feed(&metadata, &[], offset, 0)?;
-            return Ok((
-                [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
-                xml,
-            )
-                .into_response());
+                episode, "no anilist mapping found for absolute episode"
+            ),
         }
-    };
+    }
 
-    debug!(tvdb_id, season, anilist_id, "querying releases.moe");
+    for &season in &seasons {
+        debug!(tvdb_id, season, limit, "resolving plexanibridge mapping");
 
-    let fetch_limit = offset.saturating_add(limit).min(state.config.default_limit);
-    let collected: Vec<Torrent> = match state
-        .releases
-        .search_torrents(anilist_id, fetch_limit)
-        .await
-    {
-        Ok(torrents) => torrents,
-        Err(err) => {
-            tracing::error!(
-                tvdb_id,
-                season,
-                anilist_id,
-                error = %err,
-                "releases.moe lookup failed"
-            );
-            return Err(HttpError::Releases(err));
+        match state
+            .mappings
+            .resolve_anilist_id(tvdb_id, season)
+            .await
+            .map_err(HttpError::Mapping)?
+        {
+            Some(anilist_id) => {
+                if !targets.iter().any(|(_, existing)| *existing == anilist_id) {
+                    targets.push((season, anilist_id));
+                }
+            }
+            None => info!(tvdb_id, season, "no anilist mapping found for season"),
         }
-    };
-
-    let media_lookup = state
-        .anilist
-        .fetch_media(&[anilist_id])
-        .await
-        .map_err(HttpError::AniList)?;
+    }
 
-    let Some(media) = media_lookup.get(&anilist_id) else {
+    if targets.is_empty() {
         info!(
             tvdb_id,
-            season, anilist_id, "AniList media missing; returning empty result set"
+            seasons = ?seasons,
+            "no anilist mapping found; returning empty result set"
         );
         let xml = torznab::render_feed(&metadata, &[], offset, 0)?;
         return Ok((
@@ -514,42 +1080,113 @@ async fn respond_tv_search(state: &AppState, query: &TorznabQuery) -> Result<Res
             xml,
         )
             .into_response());
-    };
+    }
 
-    if !format_allowed(&media.format) {
-        info!(
-            tvdb_id,
-            season,
-            anilist_id,
-            format = ?media.format,
-            "AniList format currently unsupported; returning empty result set"
-        );
-        let xml = torznab::render_feed(&metadata, &[], offset, 0)?;
-        return Ok((
-            [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
-            xml,
-        )
-            .into_response());
+    let anilist_ids: Vec<i64> = targets.iter().map(|(_, anilist_id)| *anilist_id).collect();
+    let media_lookup = fetch_media(state, &anilist_ids).await?;
+
+    let fetch_limit = offset.saturating_add(limit).min(state.config.max_limit);
+    let mut collected: Vec<Torrent> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut torrent_seasons: HashMap<String, u32> = HashMap::new();
+    let mut feed_titles: HashMap<u32, Option<String>> = HashMap::new();
+
+    for (season, anilist_id) in targets {
+        let Some(media) = media_lookup.get(&anilist_id) else {
+            info!(
+                tvdb_id,
+                season, anilist_id, "AniList media missing; skipping season"
+            );
+            continue;
+        };
+
+        if !format_allowed(&media.format) {
+            info!(
+                tvdb_id,
+                season,
+                anilist_id,
+                format = ?media.format,
+                "AniList format currently unsupported; skipping season"
+            );
+            continue;
+        }
+
+        debug!(tvdb_id, season, anilist_id, "querying releases.moe");
+
+        let torrents = match state
+            .releases
+            .search_torrents(anilist_id, fetch_limit)
+            .await
+        {
+            Ok(torrents) => torrents,
+            Err(err) => {
+                tracing::error!(
+                    tvdb_id,
+                    season,
+                    anilist_id,
+                    error = %err,
+                    "releases.moe lookup failed"
+                );
+                return Err(HttpError::Releases(err));
+            }
+        };
+
+        feed_titles.insert(season, resolve_feed_title(state, tvdb_id, season).await?);
+
+        for torrent in torrents {
+            if seen.insert(torrent.id.clone()) {
+                torrent_seasons.insert(torrent.id.clone(), season);
+                collected.push(torrent);
+            }
+        }
     }
 
     debug!(
         tvdb_id,
-        season,
+        seasons = ?seasons,
         matches = collected.len(),
         "prepared torznab feed items"
     );
 
     let total = collected.len();
-    let feed_title = resolve_feed_title(state, tvdb_id, season).await?;
 
-    let items: Vec<TorznabItem> = collected
+    let collected: Vec<Torrent> = collected
         .into_iter()
-        .filter(|item| item.files.len() > 1)
-        .skip(offset)
-        .take(limit)
-        .map(|torrent| build_torznab_item(torrent, feed_title.clone(), tv_category_ids()))
+        .filter(|item| {
+            season_pack_allowed(state, item)
+                && !(state.config.skip_incomplete && item.incomplete)
+                && query.size_allowed(item.size_bytes)
+                && state.release_filter.allows(item)
+        })
         .collect();
-    let xml = torznab::render_feed(&metadata, &items, offset, total)?;
+    let collected = apply_best_only(state, collected);
+    let page: Vec<Torrent> = collected.into_iter().skip(offset).take(limit).collect();
+    let page = apply_nyaa_peers(state, page).await;
+    if page.is_empty() && offset == 0 && state.config.empty_as_error {
+        return Err(HttpError::NoResults);
+    }
+
+    let items: Vec<TorznabItem> = page
+        .into_iter()
+        .map(|torrent| {
+            let media = torrent
+                .anilist_id
+                .and_then(|anilist_id| media_lookup.get(&anilist_id));
+            let genres = media.map(|media| media.genres.clone()).unwrap_or_default();
+            let season = torrent_seasons.get(&torrent.id).copied();
+            let title = season
+                .and_then(|season| feed_titles.get(&season))
+                .cloned()
+                .flatten()
+                .unwrap_or_else(|| fallback_title(state, &torrent, media));
+            torznab_item(state, torrent, title, tv_category_ids())
+                .season(season)
+                .genres(genres)
+                .build()
+        })
+        .collect();
+    let items = query.attr_selection().apply(items);
+    let xml = feed_body(metadata, items, offset, total)?;
 
     Ok((
         [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
@@ -561,13 +1198,10 @@ async fn respond_tv_search(state: &AppState, query: &TorznabQuery) -> Result<Res
 async fn respond_movie_search(
     state: &AppState,
     query: &TorznabQuery,
+    headers: &HeaderMap,
 ) -> Result<Response, HttpError> {
-    let metadata = build_channel_metadata(state)?;
-    let limit = query
-        .limit
-        .unwrap_or(state.config.default_limit)
-        .max(1)
-        .min(state.config.default_limit);
+    let metadata = build_channel_metadata(state, headers)?;
+    let limit = query.page_limit(state.config.search_limit, &state.config);
 
     let offset = query.offset.unwrap_or(0);
 
@@ -623,7 +1257,7 @@ async fn respond_movie_search(
         anilist_id, limit, "movie-search querying releases.moe"
     );
 
-    let fetch_limit = offset.saturating_add(limit).min(state.config.default_limit);
+    let fetch_limit = offset.saturating_add(limit).min(state.config.max_limit);
     let collected: Vec<Torrent> = match state
         .releases
         .search_torrents(anilist_id, fetch_limit)
@@ -641,11 +1275,7 @@ async fn respond_movie_search(
         }
     };
 
-    let media_lookup = state
-        .anilist
-        .fetch_media(&[anilist_id])
-        .await
-        .map_err(HttpError::AniList)?;
+    let media_lookup = fetch_media(state, &[anilist_id]).await?;
 
     let Some(media) = media_lookup.get(&anilist_id) else {
         info!(
@@ -684,14 +1314,27 @@ async fn respond_movie_search(
         .await
         .map(|movie| format_movie_feed_title(&movie.title, movie.year))
         .map_err(HttpError::Radarr)?;
-    let items: Vec<TorznabItem> = collected
+    let collected: Vec<Torrent> = collected
         .into_iter()
-        .skip(offset)
-        .take(limit)
-        .map(|torrent| build_torznab_item(torrent, feed_title.clone(), movie_category_ids()))
+        .filter(|item| query.size_allowed(item.size_bytes) && state.release_filter.allows(item))
         .collect();
+    let collected = apply_best_only(state, collected);
+    let page: Vec<Torrent> = collected.into_iter().skip(offset).take(limit).collect();
+    let page = apply_nyaa_peers(state, page).await;
+    if page.is_empty() && offset == 0 && state.config.empty_as_error {
+        return Err(HttpError::NoResults);
+    }
+    let items: Vec<TorznabItem> = page
+        .into_iter()
+        .map(|torrent| {
+            torznab_item(state, torrent, feed_title.clone(), movie_category_ids())
+                .genres(media.genres.clone())
+                .build()
+        })
+        .collect();
+    let items = query.attr_selection().apply(items);
 
-    let xml = torznab::render_feed(&metadata, &items, offset, total)?;
+    let xml = feed_body(metadata, items, offset, total)?;
 
     Ok((
         [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
@@ -700,22 +1343,144 @@ async fn respond_movie_search(
         .into_response())
 }
 
+/// Serves `anilistid=` searches straight from releases.moe without the tvdb/tmdb mapping
+/// step, which helps tell mapping problems apart from missing releases.moe data.
+async fn respond_anilist_search(
+    state: &AppState,
+    query: &TorznabQuery,
+    headers: &HeaderMap,
+    anilist_id: i64,
+) -> Result<Response, HttpError> {
+    let metadata = build_channel_metadata(state, headers)?;
+    let limit = query.page_limit(state.config.search_limit, &state.config);
+    let offset = query.offset.unwrap_or(0);
+
+    if !category_filter_matches(&query.cat) {
+        debug!(
+            anilist_id,
+            "anilist search category filter unsupported; returning empty feed"
+        );
+        let xml = torznab::render_feed(&metadata, &[], offset, 0)?;
+        return Ok((
+            [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+            xml,
+        )
+            .into_response());
+    }
+
+    let media_lookup = fetch_media(state, &[anilist_id]).await?;
+
+    let Some(media) = media_lookup.get(&anilist_id) else {
+        info!(
+            anilist_id,
+            "AniList media missing; returning empty result set"
+        );
+        let xml = torznab::render_feed(&metadata, &[], offset, 0)?;
+        return Ok((
+            [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+            xml,
+        )
+            .into_response());
+    };
+
+    let is_tv = format_allowed(&media.format);
+    if !is_tv && !movie_format_allowed(&media.format) {
+        info!(
+            anilist_id,
+            format = ?media.format,
+            "AniList format currently unsupported; returning empty result set"
+        );
+        let xml = torznab::render_feed(&metadata, &[], offset, 0)?;
+        return Ok((
+            [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+            xml,
+        )
+            .into_response());
+    }
+
+    debug!(anilist_id, limit, "anilist search querying releases.moe");
+
+    let fetch_limit = offset.saturating_add(limit).min(state.config.max_limit);
+    let collected = state
+        .releases
+        .search_torrents(anilist_id, fetch_limit)
+        .await
+        .map_err(HttpError::Releases)?;
+
+    let total = collected.len();
+    let collected: Vec<Torrent> = collected
+        .into_iter()
+        .filter(|item| {
+            (!is_tv || season_pack_allowed(state, item))
+                && query.size_allowed(item.size_bytes)
+                && state.release_filter.allows(item)
+        })
+        .collect();
+    let collected = apply_best_only(state, collected);
+    let page: Vec<Torrent> = collected.into_iter().skip(offset).take(limit).collect();
+    let page = apply_nyaa_peers(state, page).await;
+
+    let feed_title = media
+        .title
+        .as_deref()
+        .map(|title| format!("{title} Bluray 1080p remux"));
+    let categories = if is_tv {
+        tv_category_ids()
+    } else {
+        movie_category_ids()
+    };
+    let items: Vec<TorznabItem> = page
+        .into_iter()
+        .map(|torrent| {
+            let title = feed_title
+                .clone()
+                .unwrap_or_else(|| fallback_title(state, &torrent, Some(media)));
+            torznab_item(state, torrent, title, categories.clone())
+                .genres(media.genres.clone())
+                .build()
+        })
+        .collect();
+    let items = query.attr_selection().apply(items);
+
+    let xml = feed_body(metadata, items, offset, total)?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+        xml,
+    )
+        .into_response())
+}
+
+/// Resolves the Sonarr-derived feed title for a series season. Returns `None` when Sonarr
+/// has no series for the tvdb id so callers can fall back to a default title; transport
+/// errors are still propagated.
 async fn resolve_feed_title(
     state: &AppState,
     tvdb_id: i64,
     season: u32,
-) -> Result<String, HttpError> {
+) -> Result<Option<String>, HttpError> {
     debug!(tvdb_id, season, "resolving title from sonarr");
     let sonarr = state
         .sonarr
         .as_ref()
         .ok_or_else(|| HttpError::UnsupportedOperation("Sonarr is disabled".to_string()))?;
-    let series_title = sonarr
-        .resolve_name(tvdb_id)
-        .await
-        .map_err(HttpError::Sonarr)?;
+    let series_title = match sonarr.resolve_name(tvdb_id).await {
+        Ok(title) => title,
+        Err(SonarrError::NotFound { .. }) => {
+            info!(
+                tvdb_id,
+                season, "Sonarr has no series for tvdb id; using fallback title"
+            );
+            return Ok(None);
+        }
+        Err(err) => return Err(HttpError::Sonarr(err)),
+    };
     debug!(tvdb_id, %series_title, "resolved series title from sonarr");
-    Ok(format!("{series_title} S{season:02} Bluray 1080p remux"))
+    Ok(Some(format_series_feed_title(&series_title, season)))
+}
+
+fn format_series_feed_title(series_title: &str, season: u32) -> String {
+    format!("{series_title} S{season:02} Bluray 1080p remux")
 }
 
 fn format_movie_feed_title(title: &str, year: u32) -> String {
@@ -726,123 +1491,373 @@ fn format_movie_feed_title(title: &str, year: u32) -> String {
     }
 }
 
-fn build_channel_metadata(state: &AppState) -> Result<ChannelMetadata, HttpError> {
-    let base = match state.config.public_base_url.clone() {
-        Some(url) => url,
-        None => Url::parse(&format!("http://{}", state.config.listen_addr))
-            .map_err(|err| HttpError::BaseUrl(err.to_string()))?,
-    };
+fn feed_body(
+    metadata: ChannelMetadata,
+    items: Vec<TorznabItem>,
+    offset: usize,
+    total: usize,
+) -> Result<Body, HttpError> {
+    if items.len() >= STREAM_FEED_MIN_ITEMS {
+        return Ok(torznab::stream_feed(metadata, items, offset, total));
+    }
+    Ok(Body::from(torznab::render_feed(
+        &metadata, &items, offset, total,
+    )?))
+}
 
-    let site_link = base.clone();
+fn build_channel_metadata(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<ChannelMetadata, HttpError> {
+    let site_link = public_base_url(state, headers)?;
     Ok(ChannelMetadata {
         title: state.config.application_title.clone(),
         description: state.config.application_description.clone(),
         site_link: site_link.to_string(),
+        default_limit: state.config.default_limit,
+        max_limit: state.config.max_limit,
+        fallback_published: state.config.pubdate_fallback.then(OffsetDateTime::now_utc),
+        categories: caps_categories(state),
+        searching: searching_caps(state),
     })
 }
 
-async fn resolve_tv_generic_title(
-    state: &AppState,
-    torrent: &crate::releases::Torrent,
-    cache: &mut HashMap<(i64, u32), String>,
-    active_tvdb_ids: &mut HashSet<i64>,
-) -> Result<String, HttpError> {
-    let Some(anilist_id) = torrent.anilist_id else {
-        return Ok(default_torrent_title(&torrent.id));
+/// Base for absolute links: `SEADEXER_PUBLIC_BASE_URL` when set, then the proxy's
+/// `X-Forwarded-Proto`/`X-Forwarded-Host` if `SEADEXER_TRUST_FORWARDED` is on, and finally
+/// `http://` plus the listen address.
+fn public_base_url(state: &AppState, headers: &HeaderMap) -> Result<Url, HttpError> {
+    if let Some(url) = &state.config.public_base_url {
+        return Ok(url.clone());
+    }
+    if state.config.trust_forwarded
+        && let Some(url) = forwarded_base_url(headers)
+    {
+        return Ok(url);
+    }
+    Url::parse(&format!("http://{}", state.config.listen_addr))
+        .map_err(|err| HttpError::BaseUrl(err.to_string()))
+}
+
+/// The Torznab endpoint under the channel's base URL. The base always ends in `/`, so a
+/// path prefix such as `/seadex/` is kept.
+fn torznab_url(metadata: &ChannelMetadata) -> Result<Url, HttpError> {
+    Url::parse(&metadata.site_link)
+        .and_then(|base| base.join("api"))
+        .map_err(|err| HttpError::BaseUrl(err.to_string()))
+}
+
+/// Proxies chained together append to these headers; the first entry is the client-facing one.
+fn forwarded_base_url(headers: &HeaderMap) -> Option<Url> {
+    let first_value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
     };
 
-    let mappings = state
-        .mappings
-        .resolve_tvdb_mappings(anilist_id)
-        .await
-        .map_err(HttpError::Mapping)?;
+    let proto = first_value("x-forwarded-proto");
+    let host = first_value("x-forwarded-host");
+    if proto.is_none() && host.is_none() {
+        return None;
+    }
 
-    if mappings.is_empty() {
-        return Ok(default_torrent_title(&torrent.id));
+    let proto = proto.unwrap_or("http").to_ascii_lowercase();
+    if proto != "http" && proto != "https" {
+        return None;
     }
+    let host = host.or_else(|| first_value(header::HOST.as_str()))?;
+    Url::parse(&format!("{proto}://{host}/")).ok()
+}
 
-    if let Some((tvdb_id, season)) = select_tvdb_and_season(&mappings) {
-        active_tvdb_ids.insert(tvdb_id);
+/// Categories as this instance actually serves them, gated on the apps like
+/// [`searching_caps`]: TV/Anime with Sonarr and Movies with Radarr.
+fn caps_categories(state: &AppState) -> Vec<TorznabCategory> {
+    let mut categories = Vec::new();
+    if state.sonarr.is_some() {
+        categories.push(torznab::ANIME_CATEGORY);
+    }
+    if state.radarr.is_some() {
+        categories.push(torznab::MOVIE_CATEGORY);
+    }
+    categories
+}
 
-        if let Some(existing) = cache.get(&(tvdb_id, season)) {
-            return Ok(existing.clone());
-        }
+/// Search functions and parameters as this instance actually serves them: tv-search needs
+/// Sonarr and movie-search needs Radarr. Free-text `q` isn't supported, so it isn't listed.
+fn searching_caps(state: &AppState) -> SearchingCaps {
+    SearchingCaps {
+        search: Some(vec!["anilistid", "minsize", "maxsize"]),
+        tv_search: state.sonarr.is_some().then(|| {
+            let mut params = vec![
+                "tvdbid",
+                "tvmazeid",
+                "rid",
+                "season",
+                "anilistid",
+                "minsize",
+                "maxsize",
+            ];
+            if state.config.absolute_numbering {
+                params.push("ep");
+            }
+            params
+        }),
+        movie_search: state
+            .radarr
+            .is_some()
+            .then(|| vec!["tmdbid", "anilistid", "minsize", "maxsize"]),
+    }
+}
 
-        let title = resolve_feed_title(state, tvdb_id, season).await?;
-        cache.insert((tvdb_id, season), title.clone());
-        return Ok(title);
+/// Collects the tvdb and tmdb ids the given torrents map to, for pruning the title caches.
+async fn collect_active_ids(
+    state: &AppState,
+    torrents: &[Torrent],
+    media_lookup: &HashMap<i64, AniListMedia>,
+) -> Result<(HashSet<i64>, HashSet<i64>), HttpError> {
+    let mut tvdb_ids = HashSet::new();
+    let mut tmdb_ids = HashSet::new();
+    let anilist_ids: HashSet<i64> = torrents
+        .iter()
+        .filter_map(|torrent| torrent.anilist_id)
+        .collect();
+
+    for anilist_id in anilist_ids {
+        let Some(media) = media_lookup.get(&anilist_id) else {
+            continue;
+        };
+
+        match &media.format {
+            MediaFormat::Movie => {
+                if let Some(tmdb_id) = state
+                    .mappings
+                    .resolve_tmdb_id(anilist_id)
+                    .await
+                    .map_err(HttpError::Mapping)?
+                {
+                    tmdb_ids.insert(tmdb_id);
+                }
+            }
+            format if format_allowed(format) => {
+                let mappings = state
+                    .mappings
+                    .resolve_tvdb_mappings(anilist_id)
+                    .await
+                    .map_err(HttpError::Mapping)?;
+                let selected = select_tvdb_and_season(&mappings, state.config.season_strategy);
+                tvdb_ids.extend(selected.into_iter().map(|(tvdb_id, _)| tvdb_id));
+            }
+            _ => {}
+        }
     }
 
-    Ok(default_torrent_title(&torrent.id))
+    Ok((tvdb_ids, tmdb_ids))
 }
 
-async fn resolve_movie_generic_title(
-    state: &AppState,
-    anilist_id: i64,
-    cache: &mut HashMap<i64, String>,
-    active_tmdb_ids: &mut HashSet<i64>,
-) -> Result<Option<String>, HttpError> {
-    let Some(tmdb_id) = state
-        .mappings
-        .resolve_tmdb_id(anilist_id)
-        .await
-        .map_err(HttpError::Mapping)?
-    else {
-        return Ok(None);
-    };
+/// Where a generic-search item gets its title from.
+enum TitleSource {
+    /// No Sonarr/Radarr for the entry's format; the AniList fallback title is used.
+    Fallback,
+    /// The mapped tvdb series and seasons, one feed item per distinct title.
+    Series(Vec<(i64, u32)>),
+    /// The mapped tmdb movie, if any.
+    Movie(Option<i64>),
+}
 
-    if let Some(existing) = cache.get(&tmdb_id) {
-        active_tmdb_ids.insert(tmdb_id);
-        return Ok(Some(existing.clone()));
+/// Looks up every distinct Sonarr series and Radarr movie the planned items need, at most
+/// `SEADEXER_ARR_CONCURRENCY` at a time. Ids the *arr doesn't know map to `None`; any other
+/// failure fails the feed.
+async fn resolve_arr_titles(
+    state: &AppState,
+    planned: &[(Torrent, &AniListMedia, TitleSource)],
+) -> Result<(HashMap<i64, Option<String>>, HashMap<i64, Option<String>>), HttpError> {
+    let mut tvdb_ids = HashSet::new();
+    let mut tmdb_ids = HashSet::new();
+    for (_, _, source) in planned {
+        match source {
+            TitleSource::Series(seasons) => {
+                tvdb_ids.extend(seasons.iter().map(|(tvdb_id, _)| *tvdb_id));
+            }
+            TitleSource::Movie(Some(tmdb_id)) => {
+                tmdb_ids.insert(*tmdb_id);
+            }
+            TitleSource::Movie(None) | TitleSource::Fallback => {}
+        }
     }
 
-    let radarr = state
-        .radarr
-        .as_ref()
-        .ok_or_else(|| HttpError::UnsupportedOperation("Radarr is disabled".to_string()))?;
+    let permits = Semaphore::new(state.config.arr_concurrency);
+    let series = try_join_all(tvdb_ids.into_iter().map(|tvdb_id| {
+        let permits = &permits;
+        async move {
+            let sonarr = state
+                .sonarr
+                .as_ref()
+                .ok_or_else(|| HttpError::UnsupportedOperation("Sonarr is disabled".to_string()))?;
+            let _permit = permits.acquire().await.expect("semaphore is never closed");
+            match sonarr.resolve_name(tvdb_id).await {
+                Ok(title) => Ok((tvdb_id, Some(title))),
+                Err(SonarrError::NotFound { .. }) => {
+                    info!(
+                        tvdb_id,
+                        "Sonarr has no series for tvdb id; using fallback title"
+                    );
+                    Ok((tvdb_id, None))
+                }
+                Err(err) => Err(HttpError::Sonarr(err)),
+            }
+        }
+    }));
+    let movies = try_join_all(tmdb_ids.into_iter().map(|tmdb_id| {
+        let permits = &permits;
+        async move {
+            let radarr = state
+                .radarr
+                .as_ref()
+                .ok_or_else(|| HttpError::UnsupportedOperation("Radarr is disabled".to_string()))?;
+            let _permit = permits.acquire().await.expect("semaphore is never closed");
+            match radarr.resolve_name(tmdb_id).await {
+                Ok(movie) => Ok((
+                    tmdb_id,
+                    Some(format_movie_feed_title(&movie.title, movie.year)),
+                )),
+                Err(RadarrError::NotFound { .. }) => Ok((tmdb_id, None)),
+                Err(err) => Err(HttpError::Radarr(err)),
+            }
+        }
+    }));
+    let (series, movies) = tokio::try_join!(series, movies)?;
+
+    Ok((series.into_iter().collect(), movies.into_iter().collect()))
+}
 
-    let movie = match radarr.resolve_name(tmdb_id).await {
-        Ok(movie) => movie,
-        Err(RadarrError::NotFound { .. }) => return Ok(None),
-        Err(err) => return Err(HttpError::Radarr(err)),
+/// With `SEADEXER_SONARR_LIBRARY_ONLY`, checks that one of the entry's tvdb series is
+/// monitored in Sonarr.
+async fn in_sonarr_library(state: &AppState, anilist_id: i64) -> Result<bool, HttpError> {
+    let Some(sonarr) = state.sonarr.as_ref().filter(|sonarr| sonarr.library_only()) else {
+        return Ok(true);
     };
 
-    let formatted = format_movie_feed_title(&movie.title, movie.year);
-    cache.insert(tmdb_id, formatted.clone());
-    active_tmdb_ids.insert(tmdb_id);
-    Ok(Some(formatted))
-}
+    let mappings = state
+        .mappings
+        .resolve_tvdb_mappings(anilist_id)
+        .await
+        .map_err(HttpError::Mapping)?;
+
+    for mapping in &mappings {
+        if sonarr.in_library(mapping.tvdb_id).await {
+            return Ok(true);
+        }
+    }
 
-fn select_tvdb_and_season(mappings: &[TvdbMapping]) -> Option<(i64, u32)> {
-    let mut best: Option<(i64, u32)> = None;
+    Ok(false)
+}
 
-    for mapping in mappings {
-        let mut seasons: Vec<u32> = mapping
+fn select_tvdb_and_season(mappings: &[TvdbMapping], strategy: SeasonStrategy) -> Vec<(i64, u32)> {
+    strategy.select(mappings.iter().map(|mapping| {
+        let seasons = mapping
             .seasons
             .iter()
             .filter_map(|key| parse_season_key(key))
             .collect();
+        (mapping.tvdb_id, seasons)
+    }))
+}
 
-        if seasons.is_empty() {
-            continue;
-        }
+/// Title for a release no *arr could name: `SEADEXER_FALLBACK_TITLE_TEMPLATE` when all of
+/// its placeholders resolve, then the AniList title, then `Torrent {id}`.
+fn fallback_title(state: &AppState, torrent: &Torrent, media: Option<&AniListMedia>) -> String {
+    let anilist_title = media.and_then(|media| anilist_title(torrent, media));
 
-        seasons.sort_unstable();
-        let season = seasons[0];
+    if let Some(template) = &state.config.fallback_title_template
+        && let Some(title) = render_title_template(template, torrent, anilist_title.as_deref())
+    {
+        return title;
+    }
 
-        match best {
-            Some((_, current)) if season >= current => {}
-            _ => best = Some((mapping.tvdb_id, season)),
-        }
+    match anilist_title {
+        Some(title) => format!("{title} Bluray 1080p remux"),
+        None => default_torrent_title(&torrent.id),
     }
+}
+
+/// AniList title for a release: the series name its files use when that is one of the
+/// media's titles or synonyms, since that's the spelling groups and Sonarr's aliases
+/// share, otherwise the romaji title.
+fn anilist_title(torrent: &Torrent, media: &AniListMedia) -> Option<String> {
+    file_series_name(torrent)
+        .filter(|name| anilist::matches_title(media, name))
+        .or_else(|| media.romaji.clone())
+}
+
+/// Series name from a release's first file, e.g. `Frieren` from
+/// `[Group] Frieren - 01 (1080p).mkv`: bracketed tags and the extension are dropped, and
+/// the name ends at a ` - ` separator or the first episode or season number.
+fn file_series_name(torrent: &Torrent) -> Option<String> {
+    let name = torrent.files.first()?.name.rsplit('/').next()?;
+    let stem = name
+        .rsplit_once('.')
+        .filter(|(_, extension)| extension.len() <= 4)
+        .map_or(name, |(stem, _)| stem);
+
+    let mut depth = 0usize;
+    let untagged: String = stem
+        .chars()
+        .filter_map(|ch| match ch {
+            '[' | '(' => {
+                depth += 1;
+                None
+            }
+            ']' | ')' => {
+                depth = depth.saturating_sub(1);
+                Some(' ')
+            }
+            '.' | '_' => (depth == 0).then_some(' '),
+            _ => (depth == 0).then_some(ch),
+        })
+        .collect();
 
-    best
+    let is_number = |word: &str| {
+        let digits = word.strip_prefix(['s', 'S', 'e', 'E']).unwrap_or(word);
+        digits.starts_with(|ch: char| ch.is_ascii_digit())
+    };
+    let words: Vec<&str> = untagged
+        .split_whitespace()
+        .take_while(|word| *word != "-" && !is_number(word))
+        .collect();
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+/// Fills `{id}`, `{anilist_id}` and `{title}` (the AniList title) into `template`, or
+/// `None` when a placeholder it uses has no value for this release.
+fn render_title_template(
+    template: &str,
+    torrent: &Torrent,
+    anilist_title: Option<&str>,
+) -> Option<String> {
+    let mut title = template.replace("{id}", &torrent.id);
+    if title.contains("{anilist_id}") {
+        title = title.replace("{anilist_id}", &torrent.anilist_id?.to_string());
+    }
+    if title.contains("{title}") {
+        title = title.replace("{title}", anilist_title?);
+    }
+    Some(title)
 }
 
 fn default_torrent_title(id: &str) -> String {
     format!("Torrent {id}")
 }
 
+/// Whether a release can be offered as a tv season pack: it has at least
+/// `SEADEXER_MIN_PACK_FILES` files, and with `SEADEXER_SPECIALS_IN_PACKS=exclude` doesn't
+/// bundle specials.
+fn season_pack_allowed(state: &AppState, torrent: &Torrent) -> bool {
+    torrent.files.len() >= state.config.min_pack_files
+        && !(state.config.specials_in_packs == SpecialsInPacks::Exclude && torrent.mixes_specials())
+}
+
 fn tv_category_ids() -> Vec<u32> {
     let mut ids = vec![torznab::ANIME_CATEGORY.id];
     if let Some(sub) = torznab::ANIME_CATEGORY.subcategories.first() {
@@ -855,42 +1870,152 @@ fn movie_category_ids() -> Vec<u32> {
     vec![torznab::MOVIE_CATEGORY.id]
 }
 
-fn build_torznab_item(
+/// Starts a feed item for `torrent`; callers add any extended attrs before building.
+fn torznab_item(
+    state: &AppState,
     torrent: crate::releases::Torrent,
     title: String,
     categories: Vec<u32>,
-) -> TorznabItem {
+) -> TorznabItemBuilder {
+    let score = score_torrent(&torrent, &state.config);
+    let mixes_specials = torrent.mixes_specials();
+    let guid = torrent_guid(state, &torrent);
     let crate::releases::Torrent {
-        id,
+        id: _,
         download_url,
         source_url,
         info_hash,
         published,
         size_bytes,
         is_best,
+        dual_audio: _,
         files: _,
-        anilist_id: _,
+        release_group: _,
+        anilist_id,
+        peers,
+        incomplete: _,
     } = torrent;
 
-    let seeders = if is_best { 1000 } else { 100 };
-    let comments = if source_url.is_empty() {
-        None
-    } else {
-        Some(source_url)
+    let (seeders, leechers) = match peers {
+        Some(counts) => (counts.seeders, counts.leechers),
+        None => (score, 0),
+    };
+    // releases.moe serves an entry's page at its AniList id
+    let seadex_page = anilist_id
+        .filter(|_| state.config.comments_source == CommentsSource::Seadex)
+        .and_then(|anilist_id| {
+            state
+                .config
+                .releases_base_url
+                .join(&format!("/{anilist_id}"))
+                .ok()
+        });
+    let comments = match seadex_page {
+        Some(url) => Some(url.to_string()),
+        None if source_url.is_empty() => None,
+        None => Some(source_url),
     };
 
-    TorznabItem {
-        title,
-        guid: id,
-        link: download_url,
-        comments,
-        published,
-        size_bytes,
-        info_hash,
-        seeders,
-        leechers: 0,
-        categories,
+    // best picks are advertised as freeleech so quality profiles lean towards them
+    let download_volume_factor =
+        state
+            .config
+            .best_as_freeleech
+            .then_some(if is_best { 0 } else { 1 });
+
+    TorznabItem::builder(guid, title, download_url)
+        .comments(comments)
+        .published(published)
+        .size_bytes(size_bytes)
+        .info_hash(info_hash)
+        .peers(seeders, leechers)
+        .categories(categories)
+        .download_volume_factor(download_volume_factor)
+        .mixes_specials(mixes_specials)
+}
+
+/// The `<guid>` for `torrent` under `SEADEXER_GUID_SOURCE`.
+fn torrent_guid(state: &AppState, torrent: &Torrent) -> String {
+    let info_hash = torrent
+        .info_hash
+        .as_deref()
+        .map(str::trim)
+        .filter(|hash| !hash.is_empty());
+    match (state.config.guid_source, info_hash) {
+        (GuidSource::InfoHash, Some(hash)) => hash.to_ascii_lowercase(),
+        _ => torrent.id.clone(),
+    }
+}
+
+/// With `SEADEXER_BEST_ONLY`, keeps only SeaDex "best" picks for each AniList entry.
+/// Entries without a best pick keep all results unless `SEADEXER_BEST_ONLY_FALLBACK=false`.
+fn apply_best_only(state: &AppState, torrents: Vec<Torrent>) -> Vec<Torrent> {
+    if !state.config.best_only {
+        return torrents;
     }
+
+    let with_best: HashSet<Option<i64>> = torrents
+        .iter()
+        .filter(|torrent| torrent.is_best)
+        .map(|torrent| torrent.anilist_id)
+        .collect();
+
+    torrents
+        .into_iter()
+        .filter(|torrent| {
+            if with_best.contains(&torrent.anilist_id) {
+                torrent.is_best
+            } else {
+                state.config.best_only_fallback
+            }
+        })
+        .collect()
+}
+
+/// Replaces the synthetic peer counts with real ones scraped from nyaa and drops
+/// torrents below the configured seeder floor. Disabled unless `SEADEXER_NYAA_SEEDERS`
+/// is set; scrape failures keep the torrent with its synthetic values.
+async fn apply_nyaa_peers(state: &AppState, torrents: Vec<Torrent>) -> Vec<Torrent> {
+    let Some(nyaa) = &state.nyaa else {
+        return torrents;
+    };
+
+    // `buffered` keeps the feed order while a few pages are scraped at once
+    let scraped: Vec<Option<Torrent>> = stream::iter(torrents)
+        .map(|mut torrent| async move {
+            let Some(nyaa_id) = extract_nyaa_id(&torrent.source_url).map(str::to_owned) else {
+                return Some(torrent);
+            };
+
+            match nyaa.peer_counts(&nyaa_id).await {
+                Ok(counts) if counts.seeders < state.config.min_seeders => {
+                    debug!(
+                        torrent_id = %torrent.id,
+                        nyaa_id,
+                        seeders = counts.seeders,
+                        min_seeders = state.config.min_seeders,
+                        "dropping torrent below minimum seeders"
+                    );
+                    return None;
+                }
+                Ok(counts) => torrent.peers = Some(counts),
+                Err(err) => {
+                    warn!(
+                        torrent_id = %torrent.id,
+                        nyaa_id,
+                        error = %err,
+                        "failed to scrape nyaa peer counts; keeping synthetic values"
+                    );
+                }
+            }
+
+            Some(torrent)
+        })
+        .buffered(NYAA_SCRAPE_CONCURRENCY)
+        .collect()
+        .await;
+
+    scraped.into_iter().flatten().collect()
 }
 
 fn category_filter_matches(cat_param: &Option<String>) -> bool {
@@ -899,7 +2024,13 @@ fn category_filter_matches(cat_param: &Option<String>) -> bool {
         Some(value) => {
             let mut matches_supported = false;
             let mut any_values = false;
-            let categories = torznab::default_categories();
+            let supported: Vec<u32> = torznab::default_categories()
+                .iter()
+                .flat_map(|category| {
+                    std::iter::once(category.id)
+                        .chain(category.subcategories.iter().map(|sub| sub.id))
+                })
+                .collect();
             for part in value.split(',') {
                 let trimmed = part.trim();
                 if trimmed.is_empty() {
@@ -909,12 +2040,10 @@ fn category_filter_matches(cat_param: &Option<String>) -> bool {
                 if trimmed == "0" {
                     return true;
                 }
-                if let Ok(id) = trimmed.parse::<u32>() {
-                    if categories.iter().any(|category| {
-                        category.id == id || category.subcategories.iter().any(|sub| sub.id == id)
-                    }) {
-                        matches_supported = true;
-                    }
+                if let Some((start, end)) = parse_category_range(trimmed)
+                    && supported.iter().any(|id| (start..=end).contains(id))
+                {
+                    matches_supported = true;
                 }
             }
 
@@ -923,6 +2052,23 @@ fn category_filter_matches(cat_param: &Option<String>) -> bool {
     }
 }
 
+/// Parses one `cat` entry into an inclusive id range: `N-M` as given, a parent id such as
+/// `5000` as itself plus its `5xxx` subcategories, and any other id as itself.
+fn parse_category_range(value: &str) -> Option<(u32, u32)> {
+    if let Some((start, end)) = value.split_once('-') {
+        let start = start.trim().parse::<u32>().ok()?;
+        let end = end.trim().parse::<u32>().ok()?;
+        return (start <= end).then_some((start, end));
+    }
+
+    let id = value.parse::<u32>().ok()?;
+    if id % 1000 == 0 {
+        Some((id, id + 999))
+    } else {
+        Some((id, id))
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum HttpError {
     #[error("unsupported torznab operation `{0}`")]
@@ -941,52 +2087,504 @@ pub enum HttpError {
     Sonarr(#[from] SonarrError),
     #[error(transparent)]
     Radarr(#[from] RadarrError),
+    /// Only raised with `SEADEXER_EMPTY_AS_ERROR`, for monitoring checks.
+    #[error("search resolved to an AniList entry but returned no releases")]
+    NoResults,
+}
+
+impl HttpError {
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            HttpError::AniList(AniListError::RateLimited { retry_after })
+            | HttpError::Releases(ReleasesError::RateLimited { retry_after }) => *retry_after,
+            _ => None,
+        }
+    }
 }
 
 impl IntoResponse for HttpError {
     fn into_response(self) -> Response {
-        let (status, message): (StatusCode, Cow<'static, str>) = match &self {
+        let (status, code, message): (StatusCode, u16, Cow<'static, str>) = match &self {
             HttpError::UnsupportedOperation(_) => {
-                (StatusCode::BAD_REQUEST, Cow::from(self.to_string()))
+                (StatusCode::BAD_REQUEST, 202, Cow::from(self.to_string()))
             }
             HttpError::BaseUrl(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
+                900,
                 Cow::from("Failed to construct public facing URL for seadexerr indexer"),
             ),
             HttpError::Mapping(_) => (
                 StatusCode::BAD_GATEWAY,
+                900,
                 Cow::from("Failed to resolve PlexAniBridge mapping for the requested query"),
             ),
+            HttpError::Releases(ReleasesError::RateLimited { .. }) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                500,
+                Cow::from("releases.moe is rate limiting requests; retry later"),
+            ),
             HttpError::Releases(ReleasesError::Url(_)) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
+                900,
                 Cow::from("Failed to construct releases.moe request"),
             ),
             HttpError::Releases(_) => (
                 StatusCode::BAD_GATEWAY,
+                900,
                 Cow::from("Failed to query releases.moe"),
             ),
             HttpError::Torznab(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
+                900,
                 Cow::from("Failed to render torznab payload"),
             ),
+            HttpError::AniList(AniListError::RateLimited { .. }) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                500,
+                Cow::from("AniList is rate limiting requests; retry later"),
+            ),
             HttpError::AniList(_) => (
                 StatusCode::BAD_GATEWAY,
+                900,
                 Cow::from("Failed to query AniList"),
             ),
             HttpError::Sonarr(SonarrError::Url(_)) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
+                900,
                 Cow::from("Failed to construct Sonarr request"),
             ),
-            HttpError::Sonarr(_) => (StatusCode::BAD_GATEWAY, Cow::from("Failed to query Sonarr")),
+            HttpError::Sonarr(_) => (
+                StatusCode::BAD_GATEWAY,
+                900,
+                Cow::from("Failed to query Sonarr"),
+            ),
             HttpError::Radarr(RadarrError::Url(_)) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
+                900,
                 Cow::from("Failed to construct Radarr request"),
             ),
-            HttpError::Radarr(_) => (StatusCode::BAD_GATEWAY, Cow::from("Failed to query Radarr")),
+            HttpError::Radarr(_) => (
+                StatusCode::BAD_GATEWAY,
+                900,
+                Cow::from("Failed to query Radarr"),
+            ),
+            HttpError::NoResults => (
+                StatusCode::NOT_FOUND,
+                300,
+                Cow::from("No releases found for the requested item"),
+            ),
         };
 
         tracing::error!("torznab handler error: {self}");
 
-        (status, message).into_response()
+        let retry_after = self.retry_after();
+        let mut response = match torznab::render_error(code, &message) {
+            Ok(xml) => (
+                status,
+                [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+                xml,
+            )
+                .into_response(),
+            Err(_) => (status, message).into_response(),
+        };
+        if let Some(retry_after) = retry_after {
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(retry_after.as_secs()),
+            );
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::Request;
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::releases::TorrentFile;
+    use crate::test_support;
+
+    const MAPPINGS: &str = r#"{"21": {"tvdb_id": 81797, "tvdb_mappings": {"s1": ""}}}"#;
+
+    /// State whose data directory holds [`MAPPINGS`], configured by `vars` on top of the
+    /// defaults. Keep the returned directory alive for as long as the state.
+    async fn test_state(vars: &[(&str, &str)]) -> (SharedAppState, TempDir) {
+        let data = tempfile::tempdir().unwrap();
+        std::fs::write(data.path().join("mappings.json"), MAPPINGS).unwrap();
+
+        let data_path = data.path().to_str().unwrap();
+        let mut config_vars = vec![
+            ("SEADEXER_DATA_PATH", data_path),
+            ("SEADEXER_MAPPING_REFRESH_SECS", "0"),
+        ];
+        config_vars.extend_from_slice(vars);
+        let state = AppState::new(AppConfig::for_tests(&config_vars))
+            .await
+            .unwrap();
+
+        (Arc::new(state), data)
+    }
+
+    async fn fetch(state: &SharedAppState, uri: &str) -> Response {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        router(state.clone()).oneshot(request).await.unwrap()
+    }
+
+    fn content_type(response: &Response) -> &str {
+        response.headers()[header::CONTENT_TYPE].to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn explicit_xml_output_is_served_as_xml() {
+        let (state, _data) = test_state(&[]).await;
+
+        let response = fetch(&state, "/api?t=caps&o=xml").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(content_type(&response), "application/xml; charset=utf-8");
+
+        let response = fetch(&state, "/api?t=tvsearch&tvdbid=81797&season=2&o=XML").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            content_type(&response),
+            "application/rss+xml; charset=utf-8"
+        );
+    }
+
+    fn cat(value: &str) -> bool {
+        category_filter_matches(&Some(value.to_owned()))
+    }
+
+    #[test]
+    fn category_filter_accepts_ids_parents_and_ranges() {
+        assert!(cat("5000"));
+        assert!(cat("5070"));
+        assert!(cat("2000"));
+        assert!(cat("5000-5999"));
+        assert!(cat("5070-5070"));
+        assert!(cat("0"));
+        assert!(!cat("5080"));
+        assert!(!cat("3000"));
+        assert!(!cat("3000-3999"));
+        assert!(!cat("5999-5000"));
+    }
+
+    #[test]
+    fn category_filter_matches_mixed_lists() {
+        assert!(cat("1000, 5070"));
+        assert!(cat("3000,5000-5999"));
+        assert!(cat("abc,2000"));
+        assert!(cat(" , "));
+        assert!(!cat("1000,3000-3999,8000"));
+        assert!(category_filter_matches(&None));
+    }
+
+    fn seasons(value: &str) -> Vec<u32> {
+        let query = TorznabQuery {
+            season: Some(value.to_owned()),
+            ..TorznabQuery::default()
+        };
+        query.season_numbers()
+    }
+
+    #[test]
+    fn season_param_accepts_an_s_prefix_and_leading_zeros() {
+        for value in ["1", "01", "S1", "s01", "s1", " S01 "] {
+            assert_eq!(seasons(value), [1], "{value}");
+        }
+        assert_eq!(seasons("S01-s03,5"), [1, 2, 3, 5]);
+        assert!(seasons("Season 1").is_empty());
+    }
+
+    fn release(file: &str) -> Torrent {
+        Torrent {
+            id: "1".to_owned(),
+            download_url: String::new(),
+            source_url: String::new(),
+            info_hash: None,
+            published: None,
+            files: vec![TorrentFile {
+                length: 1,
+                name: file.to_owned(),
+            }],
+            size_bytes: 1,
+            is_best: true,
+            dual_audio: false,
+            release_group: None,
+            anilist_id: None,
+            peers: None,
+            incomplete: false,
+        }
+    }
+
+    #[test]
+    fn anilist_titles_prefer_the_name_the_files_use() {
+        let media = AniListMedia {
+            id: 154587,
+            format: MediaFormat::Tv,
+            title: Some("Frieren: Beyond Journey's End".to_owned()),
+            romaji: Some("Sousou no Frieren".to_owned()),
+            english: Some("Frieren: Beyond Journey's End".to_owned()),
+            native: None,
+            synonyms: vec!["Frieren".to_owned()],
+            genres: Vec::new(),
+        };
+
+        for (file, expected) in [
+            (
+                "[SubsPlease] Frieren - 01 (1080p) [ABCD1234].mkv",
+                "Frieren",
+            ),
+            (
+                "Show/[Group] FRIEREN BEYOND JOURNEY'S END 01.mkv",
+                "FRIEREN BEYOND JOURNEY'S END",
+            ),
+            (
+                "Sousou.no.Frieren.S01E01.1080p.WEB.mkv",
+                "Sousou no Frieren",
+            ),
+            ("[Group] Some Other Show - 01.mkv", "Sousou no Frieren"),
+            ("01.mkv", "Sousou no Frieren"),
+        ] {
+            assert_eq!(
+                anilist_title(&release(file), &media).as_deref(),
+                Some(expected),
+                "{file}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn parameterless_api_answers_with_caps() {
+        let (state, _data) = test_state(&[]).await;
+
+        for uri in ["/api", "/api/caps", "/api?t=caps"] {
+            let response = fetch(&state, uri).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            assert_eq!(
+                content_type(&response),
+                "application/xml; charset=utf-8",
+                "{uri}"
+            );
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert!(String::from_utf8_lossy(&body).contains("<caps>"), "{uri}");
+        }
+
+        let response = fetch(&state, "/api?tvdbid=81797&season=2").await;
+        assert_eq!(
+            content_type(&response),
+            "application/rss+xml; charset=utf-8"
+        );
+    }
+
+    #[tokio::test]
+    async fn caps_categories_follow_the_enabled_apps() {
+        let caps = |state: SharedAppState| async move {
+            let response = fetch(&state, "/api?t=caps").await;
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        let (state, _data) = test_state(&[]).await;
+        let xml = caps(state).await;
+        assert!(xml.contains(r#"<category id="5000""#), "{xml}");
+        assert!(xml.contains(r#"<category id="2000""#), "{xml}");
+
+        let (state, _data) = test_state(&[("SEADEXER_RADARR_ENABLED", "false")]).await;
+        let xml = caps(state).await;
+        assert!(xml.contains(r#"<category id="5000""#), "{xml}");
+        assert!(!xml.contains(r#"<category id="2000""#), "{xml}");
+
+        let (state, _data) = test_state(&[("SEADEXER_SONARR_ENABLED", "false")]).await;
+        let xml = caps(state).await;
+        assert!(!xml.contains(r#"<category id="5000""#), "{xml}");
+        assert!(xml.contains(r#"<category id="2000""#), "{xml}");
+    }
+
+    /// Stands in for releases.moe, AniList and Sonarr at once; their paths don't overlap.
+    async fn mock_upstream() -> Url {
+        let entries = json!({
+            "page": 1,
+            "perPage": 100,
+            "totalItems": 1,
+            "totalPages": 1,
+            "items": [{
+                "alID": 21,
+                "expand": { "trs": [
+                    {
+                        "id": "t1",
+                        "url": "https://nyaa.si/view/111",
+                        "infoHash": "aaaa",
+                        "created": "2024-01-01 10:00:00.000Z",
+                        "updated": "2024-01-02 10:00:00.000Z",
+                        "isBest": true,
+                        "dualAudio": true,
+                        "tags": [],
+                        "tracker": "Nyaa",
+                        "size": 1000,
+                        "releaseGroup": "GroupA",
+                        "files": [
+                            { "name": "[GroupA] Show - 01.mkv", "length": 500 },
+                            { "name": "[GroupA] Show - 02.mkv", "length": 500 },
+                        ],
+                    },
+                    {
+                        "id": "t2",
+                        "url": "https://nyaa.si/view/222",
+                        "infoHash": "bbbb",
+                        "created": "2024-02-01 10:00:00.000Z",
+                        "isBest": false,
+                        "tags": ["Incomplete"],
+                        "tracker": "Nyaa",
+                        "size": 2000,
+                        "releaseGroup": "GroupB",
+                        "files": [
+                            { "name": "[GroupB] Show - 01.mkv", "length": 1000 },
+                            { "name": "[GroupB] Show - 02.mkv", "length": 1000 },
+                        ],
+                    },
+                ] },
+            }],
+        });
+        let media = json!({ "data": { "Page": { "media": [{
+            "id": 21,
+            "format": "TV",
+            "title": { "english": "One Piece", "romaji": "One Piece" },
+            "genres": ["Action", "Adventure"],
+        }] } } });
+        let series = json!([{ "title": "One Piece" }]);
+
+        let upstream = Router::new()
+            .route(
+                "/api/collections/entries/records",
+                get(move || async move { Json(entries) }),
+            )
+            .route("/graphql", post(move || async move { Json(media) }))
+            .route(
+                "/api/v3/series/lookup",
+                get(move || async move { Json(series) }),
+            );
+        test_support::serve(upstream).await
+    }
+
+    /// Feed for `tvdbid=81797&season=1` against [`mock_upstream`]: both releases named from
+    /// the Sonarr title, the best release ranked first, genres from AniList.
+    const EXPECTED_TV_FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:torznab="http://torznab.com/schemas/2015/feed" xmlns:newznab="http://www.newznab.com/DTD/2010/feeds/attributes/">
+  <channel>
+    <title>Seadexer</title>
+    <description>Indexer bridge for releases.moe</description>
+    <link>http://seadexer.test/</link>
+    <newznab:response offset="0" total="2"/>
+    <item>
+      <title>One Piece S01 Bluray 1080p remux</title>
+      <guid>t1</guid>
+      <link>https://nyaa.si/download/111.torrent</link>
+      <comments>https://nyaa.si/view/111</comments>
+      <pubDate>Tue, 02 Jan 2024 10:00:00 +0000</pubDate>
+      <size>1000</size>
+      <infohash>aaaa</infohash>
+      <enclosure url="https://nyaa.si/download/111.torrent" type="application/x-bittorrent" length="1000"/>
+      <torznab:attr name="category" value="5000"/>
+      <torznab:attr name="category" value="5070"/>
+      <torznab:attr name="season" value="1"/>
+      <torznab:attr name="seeders" value="1050"/>
+      <torznab:attr name="leechers" value="0"/>
+      <torznab:attr name="tag" value="internal"/>
+    </item>
+    <item>
+      <title>One Piece S01 Bluray 1080p remux</title>
+      <guid>t2</guid>
+      <link>https://nyaa.si/download/222.torrent</link>
+      <comments>https://nyaa.si/view/222</comments>
+      <pubDate>Thu, 01 Feb 2024 10:00:00 +0000</pubDate>
+      <size>2000</size>
+      <infohash>bbbb</infohash>
+      <enclosure url="https://nyaa.si/download/222.torrent" type="application/x-bittorrent" length="2000"/>
+      <torznab:attr name="category" value="5000"/>
+      <torznab:attr name="category" value="5070"/>
+      <torznab:attr name="season" value="1"/>
+      <torznab:attr name="seeders" value="100"/>
+      <torznab:attr name="leechers" value="0"/>
+      <torznab:attr name="tag" value="internal"/>
+    </item>
+  </channel>
+</rss>"#;
+
+    #[tokio::test]
+    async fn tv_search_renders_the_feed_from_upstreams() {
+        let upstream = mock_upstream().await;
+        let anilist_url = upstream.join("graphql").unwrap();
+        let (state, _data) = test_state(&[
+            ("SEADEXER_RELEASES_BASE_URL", upstream.as_str()),
+            ("SEADEXER_ANILIST_BASE_URL", anilist_url.as_str()),
+            ("SONARR_BASE_URL", upstream.as_str()),
+            ("SEADEXER_PUBLIC_BASE_URL", "http://seadexer.test/"),
+        ])
+        .await;
+
+        let response = fetch(&state, "/api?t=tvsearch&tvdbid=81797&season=1").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let xml = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(xml, EXPECTED_TV_FEED);
+    }
+
+    #[tokio::test]
+    async fn skip_incomplete_drops_partial_batches_from_tv_search() {
+        let upstream = mock_upstream().await;
+        let anilist_url = upstream.join("graphql").unwrap();
+        let (state, _data) = test_state(&[
+            ("SEADEXER_RELEASES_BASE_URL", upstream.as_str()),
+            ("SEADEXER_ANILIST_BASE_URL", anilist_url.as_str()),
+            ("SONARR_BASE_URL", upstream.as_str()),
+            ("SEADEXER_SKIP_INCOMPLETE", "true"),
+        ])
+        .await;
+
+        let response = fetch(&state, "/api?t=tvsearch&tvdbid=81797&season=1").await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let xml = String::from_utf8_lossy(&body);
+        assert!(xml.contains("https://nyaa.si/view/111"));
+        assert!(!xml.contains("https://nyaa.si/view/222"));
+    }
+
+    #[tokio::test]
+    async fn absolute_episode_stands_in_for_the_season() {
+        let upstream = mock_upstream().await;
+        let anilist_url = upstream.join("graphql").unwrap();
+        let vars = [
+            ("SEADEXER_RELEASES_BASE_URL", upstream.as_str()),
+            ("SEADEXER_ANILIST_BASE_URL", anilist_url.as_str()),
+            ("SONARR_BASE_URL", upstream.as_str()),
+            ("SEADEXER_PUBLIC_BASE_URL", "http://seadexer.test/"),
+        ];
+        let uri = "/api?t=tvsearch&tvdbid=81797&ep=5";
+
+        let (state, _data) = test_state(&vars).await;
+        let body = axum::body::to_bytes(fetch(&state, uri).await.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("<item>"));
+
+        let (state, _data) =
+            test_state(&[vars.as_slice(), &[("SEADEXER_ABSOLUTE_NUMBERING", "true")]].concat())
+                .await;
+        let body = axum::body::to_bytes(fetch(&state, uri).await.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(String::from_utf8(body.to_vec()).unwrap(), EXPECTED_TV_FEED);
     }
 }
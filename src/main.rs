@@ -1,24 +1,44 @@
+// `AppConfig::redacted` builds one large `json!` literal
+#![recursion_limit = "256"]
+
 mod anilist;
 mod config;
 mod http;
 mod mapping;
+mod nyaa;
 mod radarr;
+mod release_filter;
 mod releases;
 mod sonarr;
+#[cfg(test)]
+mod test_support;
+mod title_cache;
 mod torznab;
+mod tvmaze;
 
-use std::sync::Arc;
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 use anyhow::Context;
+use axum::body::Bytes;
+use axum_server::{Handle, tls_rustls::RustlsConfig};
+use socket2::{Domain, Protocol, Socket, Type};
 use tokio::net::TcpListener;
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::anilist::AniListClient;
 use crate::config::AppConfig;
 use crate::mapping::PlexAniBridgeMappings;
+use crate::nyaa::NyaaClient;
 use crate::radarr::RadarrClient;
+use crate::release_filter::ReleaseFilter;
 use crate::releases::ReleasesClient;
 use crate::sonarr::SonarrClient;
+use crate::tvmaze::TvMazeClient;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -27,92 +47,411 @@ pub struct AppState {
     pub sonarr: Option<SonarrClient>,
     pub radarr: Option<RadarrClient>,
     pub releases: ReleasesClient,
+    pub nyaa: Option<NyaaClient>,
+    pub tvmaze: TvMazeClient,
     pub mappings: PlexAniBridgeMappings,
+    pub release_filter: ReleaseFilter,
+    /// Rendered caps document, built on the first caps request.
+    pub caps_xml: OnceLock<Bytes>,
+}
+
+impl AppState {
+    /// Builds the upstream clients and loads the mappings `config` describes.
+    pub async fn new(config: AppConfig) -> anyhow::Result<Self> {
+        let releases =
+            ReleasesClient::new(&config).context("failed to construct releases.moe client")?;
+
+        let anilist = AniListClient::new(
+            config.anilist_base_url.clone(),
+            config.anilist_timeout,
+            config.unknown_format_as.clone(),
+            &config.outbound,
+        )
+        .context("failed to construct AniList client")?;
+
+        let nyaa = if config.nyaa_seeders {
+            Some(
+                NyaaClient::new(
+                    nyaa::default_base_url(),
+                    config.releases_timeout,
+                    &config.outbound,
+                )
+                .context("failed to construct nyaa client")?,
+            )
+        } else {
+            None
+        };
+
+        let tvmaze = TvMazeClient::new(
+            config.tvmaze_base_url.clone(),
+            config.releases_timeout,
+            &config.outbound,
+        )
+        .context("failed to construct TVmaze client")?;
+
+        let sonarr = if let Some(sonarr_config) = &config.sonarr {
+            if sonarr_config.insecure {
+                tracing::warn!(
+                    url = %sonarr_config.url,
+                    "Sonarr TLS certificate verification is DISABLED (SONARR_INSECURE=true); do not use this in production"
+                );
+            }
+
+            let sonarr_cache_path = config.cache_path.join("sonarr_titles.json");
+            let sonarr = SonarrClient::new(
+                sonarr_config,
+                sonarr_cache_path,
+                config.title_cache_max,
+                &config.outbound,
+            )
+            .context("failed to construct Sonarr client")?;
+            sonarr.spawn_library_refresh(config.prewarm);
+            Some(sonarr)
+        } else {
+            None
+        };
+
+        let radarr = if let Some(radarr_config) = &config.radarr {
+            if radarr_config.insecure {
+                tracing::warn!(
+                    url = %radarr_config.url,
+                    "Radarr TLS certificate verification is DISABLED (RADARR_INSECURE=true); do not use this in production"
+                );
+            }
+
+            let radarr_cache_path = config.cache_path.join("radarr_titles.json");
+            let radarr = RadarrClient::new(
+                radarr_config,
+                radarr_cache_path,
+                config.title_cache_max,
+                &config.outbound,
+            )
+            .context("failed to construct Radarr client")?;
+            if config.prewarm {
+                radarr.spawn_prewarm();
+            }
+            Some(radarr)
+        } else {
+            None
+        };
+
+        let mappings = PlexAniBridgeMappings::bootstrap(&config)
+            .await
+            .context("failed to initialise PlexAniBridge mappings store")?;
+
+        let release_filter = ReleaseFilter::new(&config.blocklist, &config.group_allowlist);
+
+        Ok(Self {
+            config,
+            anilist,
+            sonarr,
+            radarr,
+            releases,
+            nyaa,
+            tvmaze,
+            mappings,
+            release_filter,
+            caps_xml: OnceLock::new(),
+        })
+    }
 }
 
 pub type SharedAppState = Arc<AppState>;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    if std::env::args().skip(1).any(|arg| arg == "--healthcheck") {
+        return healthcheck().await;
+    }
+
     init_tracing();
 
     let config = AppConfig::from_env().context("failed to load configuration")?;
     let listen_addr = config.listen_addr;
-    let releases = ReleasesClient::new(
-        config.releases_base_url.clone(),
-        config.releases_timeout,
-        config.default_limit,
-    )
-    .context("failed to construct releases.moe client")?;
-
-    let anilist = AniListClient::new(config.anilist_base_url.clone(), config.anilist_timeout)
-        .context("failed to construct AniList client")?;
+    let dual_stack = config.dual_stack;
+    let listen_uds = config.listen_uds.clone();
+    let admin_addr = config.admin_addr;
+    let tls_cert_path = config.tls_cert_path.clone();
+    let tls_key_path = config.tls_key_path.clone();
 
-    let sonarr = if let Some(sonarr_config) = &config.sonarr {
-        let sonarr_cache_path = config.data_path.join("sonarr_titles.json");
-        Some(
-            SonarrClient::new(
-                sonarr_config.url.clone(),
-                sonarr_config.api_key.clone(),
-                sonarr_config.timeout,
-                sonarr_cache_path,
-            )
-            .context("failed to construct Sonarr client")?,
-        )
-    } else {
-        None
-    };
+    let state = Arc::new(AppState::new(config).await?);
+    let app = http::router(state.clone());
+    // the admin routes have no auth of their own, so they never share the public listener
+    match admin_addr {
+        Some(admin_addr) => {
+            let admin_listener = TcpListener::bind(admin_addr)
+                .await
+                .with_context(|| format!("failed to bind admin listener on {admin_addr}"))?;
+            tracing::info!("serving admin routes on {}", admin_listener.local_addr()?);
 
-    let radarr = if let Some(radarr_config) = &config.radarr {
-        let radarr_cache_path = config.data_path.join("radarr_titles.json");
-        Some(
-            RadarrClient::new(
-                radarr_config.url.clone(),
-                radarr_config.api_key.clone(),
-                radarr_config.timeout,
-                radarr_cache_path,
-            )
-            .context("failed to construct Radarr client")?,
-        )
-    } else {
-        None
-    };
+            let admin_app = http::admin_router(state.clone());
+            tokio::spawn(async move {
+                if let Err(error) = axum::serve(admin_listener, admin_app.into_make_service())
+                    .with_graceful_shutdown(shutdown_signal())
+                    .await
+                {
+                    tracing::error!(error = %error, "admin server terminated unexpectedly");
+                }
+            });
+        }
+        None => tracing::debug!("SEADEXER_ADMIN_ADDR unset; admin routes disabled"),
+    }
 
-    let mappings = PlexAniBridgeMappings::bootstrap(
-        config.data_path.clone(),
-        config.mapping_source_url.clone(),
-        config.mapping_refresh_interval,
-        config.mapping_timeout,
-    )
-    .await
-    .context("failed to initialise PlexAniBridge mappings store")?;
-
-    let state = Arc::new(AppState {
-        config,
-        anilist,
-        sonarr,
-        radarr,
-        releases,
-        mappings,
-    });
-    let app = http::router(state.clone());
+    if let Some(socket_path) = listen_uds {
+        return serve_unix(&socket_path, app).await;
+    }
 
-    let listener = TcpListener::bind(listen_addr)
+    let listener = bind_listener(listen_addr, dual_stack)
         .await
         .with_context(|| format!("failed to bind listener on {listen_addr}"))?;
 
+    if let (Some(cert_path), Some(key_path)) = (tls_cert_path, tls_key_path) {
+        return serve_tls(listener, &cert_path, &key_path, app).await;
+    }
+
     tracing::info!(
         "listening for torznab requests on {}",
         listener.local_addr()?
     );
 
     axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .context("server terminated unexpectedly")?;
 
     Ok(())
 }
 
+async fn serve_tls(
+    listener: TcpListener,
+    cert_path: &Path,
+    key_path: &Path,
+    app: axum::Router,
+) -> anyhow::Result<()> {
+    // reqwest already links rustls with ring; use the same provider for the server side.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let tls_config = RustlsConfig::from_pem_file(cert_path, key_path)
+        .await
+        .with_context(|| {
+            format!(
+                "failed to load TLS certificate {} and key {}",
+                cert_path.display(),
+                key_path.display()
+            )
+        })?;
+
+    let local_addr = listener.local_addr()?;
+    let listener = listener.into_std()?;
+
+    let handle = Handle::new();
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        shutdown_handle.graceful_shutdown(Some(Duration::from_secs(10)));
+    });
+
+    tracing::info!("listening for torznab requests on https://{local_addr}");
+
+    axum_server::from_tcp_rustls(listener, tls_config)
+        .handle(handle)
+        .serve(app.into_make_service())
+        .await
+        .context("server terminated unexpectedly")?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn serve_unix(socket_path: &Path, app: axum::Router) -> anyhow::Result<()> {
+    match remove_socket(socket_path) {
+        Ok(true) => tracing::debug!(path = %socket_path.display(), "removed stale unix socket"),
+        Ok(false) => {}
+        Err(error) => {
+            return Err(error).with_context(|| {
+                format!(
+                    "failed to remove stale unix socket {}",
+                    socket_path.display()
+                )
+            });
+        }
+    }
+
+    let listener = tokio::net::UnixListener::bind(socket_path)
+        .with_context(|| format!("failed to bind unix socket {}", socket_path.display()))?;
+
+    tracing::info!(
+        "listening for torznab requests on unix socket {}",
+        socket_path.display()
+    );
+
+    let result = axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .context("server terminated unexpectedly");
+
+    if let Err(error) = remove_socket(socket_path) {
+        tracing::warn!(
+            error = %error,
+            path = %socket_path.display(),
+            "failed to remove unix socket on shutdown"
+        );
+    }
+
+    result
+}
+
+/// Removes the socket at `path`, reporting whether there was one. Anything other than a
+/// socket is left alone and reported as an error, so a mistyped path can't delete a file.
+#[cfg(unix)]
+fn remove_socket(path: &Path) -> std::io::Result<bool> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            std::fs::remove_file(path)?;
+            Ok(true)
+        }
+        Ok(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "path exists and is not a unix socket",
+        )),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_socket_path: &Path, _app: axum::Router) -> anyhow::Result<()> {
+    anyhow::bail!("SEADEXER_LISTEN_UDS is only supported on unix platforms")
+}
+
+/// `--healthcheck`: asks the server started with the same environment for `/health` and
+/// fails unless it answers 200, so a Docker `HEALTHCHECK` doesn't need curl in the image.
+async fn healthcheck() -> anyhow::Result<()> {
+    let config = AppConfig::from_env().context("failed to load configuration")?;
+    if let Some(socket_path) = &config.listen_uds {
+        return healthcheck_unix(socket_path).await;
+    }
+
+    let mut addr = config.listen_addr;
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    let scheme = if config.tls_cert_path.is_some() && config.tls_key_path.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    let url = format!("{scheme}://{addr}/health");
+
+    // the certificate names the public host, not loopback
+    let client = reqwest::Client::builder()
+        .timeout(HEALTHCHECK_TIMEOUT)
+        .danger_accept_invalid_certs(true)
+        .no_proxy()
+        .build()?;
+    let status = client
+        .get(&url)
+        .send()
+        .await
+        .with_context(|| format!("failed to reach {url}"))?
+        .status();
+    anyhow::ensure!(status.is_success(), "{url} answered {status}");
+    Ok(())
+}
+
+const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[cfg(unix)]
+async fn healthcheck_unix(socket_path: &Path) -> anyhow::Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let exchange = async {
+        let mut stream = tokio::net::UnixStream::connect(socket_path).await?;
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = tokio::time::timeout(HEALTHCHECK_TIMEOUT, exchange)
+        .await
+        .context("health request timed out")?
+        .with_context(|| format!("failed to reach unix socket {}", socket_path.display()))?;
+
+    let status_line = response
+        .split(|byte| *byte == b'\n')
+        .next()
+        .unwrap_or_default();
+    let status_line = String::from_utf8_lossy(status_line);
+    anyhow::ensure!(
+        status_line.split_whitespace().nth(1) == Some("200"),
+        "unix socket {} answered `{}`",
+        socket_path.display(),
+        status_line.trim()
+    );
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn healthcheck_unix(_socket_path: &Path) -> anyhow::Result<()> {
+    anyhow::bail!("SEADEXER_LISTEN_UDS is only supported on unix platforms")
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(error) = tokio::signal::ctrl_c().await {
+            tracing::warn!(error = %error, "failed to listen for ctrl-c");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(error) => {
+                tracing::warn!(error = %error, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("shutdown signal received; stopping server");
+}
+
+/// Binds the TCP listener. With `dual_stack` set, an IPv6 address is bound with
+/// `IPV6_V6ONLY` cleared so `[::]` also accepts IPv4 connections regardless of the
+/// host's sysctl default.
+async fn bind_listener(addr: SocketAddr, dual_stack: bool) -> std::io::Result<TcpListener> {
+    if !(dual_stack && addr.is_ipv6()) {
+        return TcpListener::bind(addr).await;
+    }
+
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_only_v6(false)?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
 fn init_tracing() {
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
     tracing_subscriber::registry()
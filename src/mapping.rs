@@ -1,51 +1,114 @@
 use std::collections::HashMap;
 use std::io::ErrorKind;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::Context;
+use axum::body::Bytes;
+use notify::{RecursiveMode, Watcher};
+use rand::{Rng, SeedableRng, rngs::SmallRng};
 use reqwest::{
     Client, StatusCode,
     header::{ETAG, IF_NONE_MATCH},
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, RwLock, mpsc};
 use tokio::task;
-use tokio::sync::RwLock;
-use tracing::{debug, trace, warn};
+use tracing::{debug, info, trace, warn};
 use url::Url;
 
+use crate::config::AppConfig;
+
 #[derive(Debug, Clone)]
 pub struct PlexAniBridgeMappings {
     path: PathBuf,
+    overrides_path: PathBuf,
     cache: Arc<RwLock<Option<CachedMappings>>>,
+    load_lock: Arc<Mutex<()>>,
     client: Client,
-    source_url: Url,
+    source_urls: Arc<[Url]>,
+    /// Index into `source_urls` of the source that last served a response; tried first.
+    preferred_source: Arc<AtomicUsize>,
     refresh_interval: Duration,
+    refresh_jitter: f64,
+    index_cache: bool,
+    misses: Arc<RwLock<HashMap<(i64, u32), Instant>>>,
+    failed_refreshes: Arc<AtomicU64>,
+    /// Set while reloads of `mappings.json` keep failing, so the fallback warns only once.
+    reload_failing: Arc<AtomicBool>,
 }
 
+/// How long a `(tvdb_id, season)` lookup miss is remembered before the index is consulted
+/// again. Misses are also forgotten whenever a new index is loaded.
+const NEGATIVE_CACHE_TTL: Duration = Duration::from_secs(600);
+
+/// How long the watcher waits after a change to `mappings.json` before reloading it.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
 #[derive(Debug)]
 struct CachedMappings {
     modified: SystemTime,
+    overrides_modified: Option<SystemTime>,
     etag: Option<String>,
+    source: Option<Url>,
     entries: Arc<MappingIndex>,
 }
 
+/// What a persisted index snapshot was built from. It is only reused when all of it still
+/// matches, so a mappings file replaced on disk under an unchanged etag is re-parsed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SnapshotKey {
+    etag: String,
+    mappings_modified: SystemTime,
+    mappings_len: u64,
+    overrides_modified: Option<SystemTime>,
+    format: u32,
+}
+
+/// Layout version of [`MappingIndex`] snapshots. Postcard isn't self-describing, so bump it
+/// whenever the index changes shape to make older snapshots read as stale.
+const INDEX_FORMAT: u32 = 2;
+
+/// The etag recorded for the local mappings file and the source URL that served it.
 #[derive(Debug, Clone)]
+struct CachedEtag {
+    etag: String,
+    source: Option<Url>,
+}
+
+enum FetchOutcome {
+    NotModified,
+    Downloaded { etag: Option<String>, bytes: Bytes },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct MappingEntry {
     anilist_id: i64,
     seasons: Vec<String>,
+    episodes: Vec<EpisodeRange>,
 }
 
-#[derive(Debug, Clone)]
+/// A run of tvdb episodes in one season, parsed from a `tvdb_mappings` value. `last` is
+/// `None` when the run extends to the end of the season.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct EpisodeRange {
+    season: u32,
+    first: u32,
+    last: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct ReverseMappingEntry {
     tvdb_id: i64,
     seasons: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct MappingIndex {
     tvdb_to_entries: HashMap<i64, Vec<MappingEntry>>,
     anilist_to_entries: HashMap<i64, Vec<ReverseMappingEntry>>,
@@ -53,12 +116,109 @@ struct MappingIndex {
     anilist_to_tmdb: HashMap<i64, i64>,
 }
 
+/// Which mapping to use when an AniList entry or tvdb series maps to several seasons.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SeasonStrategy {
+    /// The candidate with the earliest season. Right for most shows, but continuation
+    /// series that Sonarr files under one long-running tvdb entry land on the wrong season.
+    #[default]
+    Lowest,
+    /// The candidate with the latest season. Suits continuation series, at the cost of
+    /// titling split-cour first seasons as their later part.
+    Highest,
+    /// Every candidate, each of which handlers query and merge. Most complete, but one
+    /// release can appear several times under different titles.
+    All,
+}
+
+impl SeasonStrategy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "lowest" => Some(Self::Lowest),
+            "highest" => Some(Self::Highest),
+            "all" => Some(Self::All),
+            _ => None,
+        }
+    }
+
+    /// Picks from candidates and their season numbers, ordered by season. `All` yields one
+    /// pair per mapped season; candidates without any season are skipped.
+    pub fn select<T: Clone>(
+        self,
+        candidates: impl IntoIterator<Item = (T, Vec<u32>)>,
+    ) -> Vec<(T, u32)> {
+        let mut ranked: Vec<(T, u32)> = candidates
+            .into_iter()
+            .flat_map(|(candidate, seasons)| {
+                let seasons = match self {
+                    Self::Lowest => seasons.into_iter().min().into_iter().collect(),
+                    Self::Highest => seasons.into_iter().max().into_iter().collect(),
+                    Self::All => seasons,
+                };
+                seasons
+                    .into_iter()
+                    .map(move |season| (candidate.clone(), season))
+            })
+            .collect();
+        ranked.sort_by_key(|(_, season)| *season);
+
+        match self {
+            Self::Lowest => ranked.truncate(1),
+            Self::Highest => {
+                let highest = ranked.pop();
+                ranked = highest.into_iter().collect();
+            }
+            Self::All => {}
+        }
+        ranked
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TvdbMapping {
     pub tvdb_id: i64,
     pub seasons: Vec<String>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct MappingStats {
+    pub series: usize,
+    pub anilist_ids: usize,
+    pub movies: usize,
+    pub etag: Option<String>,
+    /// Modification time of the mappings file as seconds since the Unix epoch.
+    pub modified: Option<u64>,
+}
+
+/// What the index holds around a tvdb id, for diagnosing mapping drift such as a show
+/// that TVDB merged into another id.
+#[derive(Debug, Clone, Serialize)]
+pub struct NearbyMappings {
+    pub tvdb_id: i64,
+    pub tvdb_known: bool,
+    /// The AniList id a search for the requested season would use.
+    pub season_match: Option<i64>,
+    /// Every AniList entry filed under the tvdb id.
+    pub entries: Vec<NearbyEntry>,
+    /// When the tvdb id is unknown, where the AniList entry reached through another id is
+    /// filed instead.
+    pub alternatives: Vec<AlternativeMapping>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NearbyEntry {
+    pub anilist_id: i64,
+    pub seasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlternativeMapping {
+    /// How the AniList id was reached: `tmdb` or `anilist`.
+    pub via: &'static str,
+    pub anilist_id: i64,
+    pub tvdb_ids: Vec<i64>,
+}
+
 #[derive(Debug, Deserialize)]
 struct RawMappingRecord {
     #[serde(default)]
@@ -86,156 +246,272 @@ impl TmdbMovieId {
 }
 
 impl PlexAniBridgeMappings {
-    pub async fn bootstrap(
-        data_path: PathBuf,
-        source_url: Url,
-        refresh_interval: Duration,
-        timeout: Duration,
-    ) -> anyhow::Result<Self> {
-        fs::create_dir_all(&data_path).await.with_context(|| {
-            format!("failed to create data directory at {}", data_path.display())
+    pub async fn bootstrap(config: &AppConfig) -> anyhow::Result<Self> {
+        let cache_path = &config.cache_path;
+        fs::create_dir_all(cache_path).await.with_context(|| {
+            format!(
+                "failed to create cache directory at {}",
+                cache_path.display()
+            )
         })?;
 
-        let path = data_path.join("mappings.json");
-        let client = Client::builder()
-            .timeout(timeout)
-            .user_agent(format!("seadexerr/{}", env!("CARGO_PKG_VERSION")))
-            .build()
+        let path = cache_path.join("mappings.json");
+        let overrides_path = config.data_path.join("overrides.json");
+        let client = config
+            .outbound
+            .client_builder(config.mapping_timeout)
+            .and_then(|builder| builder.build())
             .context("failed to construct PlexAniBridge HTTP client")?;
 
-        let refresh_interval = if refresh_interval.is_zero() {
-            Duration::from_secs(21_600)
-        } else {
-            refresh_interval
-        };
-
         let mappings = Self {
             path,
+            overrides_path,
             cache: Arc::new(RwLock::new(None)),
+            load_lock: Arc::new(Mutex::new(())),
             client,
-            source_url,
-            refresh_interval,
+            source_urls: config.mapping_source_urls.clone().into(),
+            preferred_source: Arc::new(AtomicUsize::new(0)),
+            refresh_interval: config.mapping_refresh_interval,
+            refresh_jitter: config.mapping_refresh_jitter,
+            index_cache: config.mapping_index_cache,
+            misses: Arc::new(RwLock::new(HashMap::new())),
+            failed_refreshes: Arc::new(AtomicU64::new(0)),
+            reload_failing: Arc::new(AtomicBool::new(false)),
         };
 
-        mappings
-            .refresh_mappings()
-            .await
-            .map_err(anyhow::Error::from)?;
-        mappings.spawn_refresh_task();
+        // on restarts, serve the copy already on disk and refresh it in the background so a
+        // slow source doesn't hold up startup; only a first run has to wait for the download
+        let refresh_now = match mappings.load_mappings().await {
+            Ok(_) => {
+                debug!(
+                    path = %mappings.path.display(),
+                    "serving local plexanibridge mappings while refreshing in the background"
+                );
+                true
+            }
+            Err(error) => {
+                if !matches!(error, MappingError::Read { ref source, .. }
+                    if source.kind() == ErrorKind::NotFound)
+                {
+                    warn!(error = %error, "failed to load local plexanibridge mappings");
+                }
+                mappings
+                    .refresh_mappings()
+                    .await
+                    .map_err(anyhow::Error::from)?;
+                false
+            }
+        };
+        if config.mapping_watch {
+            mappings
+                .spawn_watcher()
+                .context("failed to watch the plexanibridge mappings file")?;
+        }
+
+        if mappings.refresh_interval.is_zero() {
+            debug!("periodic plexanibridge mapping refresh disabled");
+        } else {
+            mappings.spawn_refresh_task(refresh_now);
+        }
 
         Ok(mappings)
     }
 
-    fn spawn_refresh_task(&self) {
+    /// Summarises the currently loaded index, or `None` before the first successful load.
+    pub async fn stats(&self) -> Option<MappingStats> {
+        let guard = self.cache.read().await;
+        let cache = guard.as_ref()?;
+
+        Some(MappingStats {
+            series: cache.entries.tvdb_to_entries.len(),
+            anilist_ids: cache.entries.anilist_to_entries.len(),
+            movies: cache.entries.tmdb_to_anilist.len(),
+            etag: cache.etag.clone(),
+            modified: cache
+                .modified
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .ok()
+                .map(|elapsed| elapsed.as_secs()),
+        })
+    }
+
+    /// Time since the loaded mappings file was last written, or `None` before the first
+    /// successful load.
+    pub async fn age(&self) -> Option<Duration> {
+        let guard = self.cache.read().await;
+        let cache = guard.as_ref()?;
+        Some(cache.modified.elapsed().unwrap_or_default())
+    }
+
+    /// Number of scheduled refreshes that have failed since startup.
+    pub fn failed_refreshes(&self) -> u64 {
+        self.failed_refreshes.load(Ordering::Relaxed)
+    }
+
+    /// Runs a refresh immediately instead of waiting for the next scheduled one.
+    pub async fn refresh(&self) -> Result<(), MappingError> {
+        self.refresh_mappings().await
+    }
+
+    /// Refreshes on a jittered interval; with `refresh_now` the first refresh runs
+    /// immediately. Not spawned when the interval is zero.
+    fn spawn_refresh_task(&self, refresh_now: bool) {
         let this = self.clone();
         tokio::spawn(async move {
+            let mut rng = SmallRng::from_os_rng();
+            let mut refresh_now = refresh_now;
             loop {
-                tokio::time::sleep(this.refresh_interval).await;
+                if !std::mem::take(&mut refresh_now) {
+                    let delay = jittered(this.refresh_interval, this.refresh_jitter, &mut rng);
+                    trace!(
+                        delay_secs = delay.as_secs(),
+                        "scheduling next mapping refresh"
+                    );
+                    tokio::time::sleep(delay).await;
+                }
                 if let Err(error) = this.refresh_mappings().await {
-                    warn!(
+                    this.failed_refreshes.fetch_add(1, Ordering::Relaxed);
+                    warn!(error = %error, "failed to refresh plexanibridge mappings");
+                }
+            }
+        });
+    }
+
+    /// Reloads the index as soon as `mappings.json` changes on disk. The parent directory is
+    /// watched rather than the file itself so replacements via rename are seen too. The
+    /// watcher lives inside the task and is dropped, stopping its thread, when the runtime
+    /// shuts down.
+    fn spawn_watcher(&self) -> notify::Result<()> {
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = events_tx.send(event);
+        })?;
+        let directory = self.path.parent().unwrap_or(Path::new("."));
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            let _watcher = watcher;
+            while let Some(event) = events_rx.recv().await {
+                let event: notify::Event = match event {
+                    Ok(event) => event,
+                    Err(error) => {
+                        warn!(error = %error, "plexanibridge mappings watcher error");
+                        continue;
+                    }
+                };
+                if event.kind.is_access() || !event.paths.contains(&this.path) {
+                    continue;
+                }
+
+                // let a writer finish before reading; changes that arrive meanwhile are
+                // covered by the same reload
+                tokio::time::sleep(WATCH_DEBOUNCE).await;
+                while events_rx.try_recv().is_ok() {}
+
+                match this.load_mappings().await {
+                    Ok(_) => trace!(path = %this.path.display(), "mappings file changed"),
+                    Err(error) => warn!(
                         error = %error,
-                        url = %this.source_url,
-                        "failed to refresh plexanibridge mappings"
-                    );
+                        path = %this.path.display(),
+                        "failed to reload plexanibridge mappings after a change"
+                    ),
                 }
             }
         });
+
+        debug!(path = %self.path.display(), "watching plexanibridge mappings file");
+        Ok(())
     }
 
     async fn refresh_mappings(&self) -> Result<(), MappingError> {
         let etag_path = self.etag_path();
         let cached_etag = {
             let guard = self.cache.read().await;
-            guard.as_ref().and_then(|cache| cache.etag.clone())
+            guard.as_ref().and_then(|cache| {
+                cache.etag.clone().map(|etag| CachedEtag {
+                    etag,
+                    source: cache.source.clone(),
+                })
+            })
         };
-        let cached_etag = if let Some(etag) = cached_etag {
-            Some(etag)
-        } else {
-            match fs::read_to_string(&etag_path).await {
-                Ok(value) => {
-                    let trimmed = value.trim();
-                    if trimmed.is_empty() {
-                        None
-                    } else {
-                        Some(trimmed.to_owned())
-                    }
-                }
-                Err(error) if error.kind() == ErrorKind::NotFound => None,
-                Err(error) => {
-                    warn!(
-                        error = %error,
-                        path = %etag_path.display(),
-                        "failed to read cached etag; proceeding without conditional request"
-                    );
-                    None
-                }
+        let cached_etag = match cached_etag {
+            Some(cached) => Some(cached),
+            None => {
+                self.read_etag("failed to read cached etag; proceeding without conditional request")
+                    .await
             }
         };
 
-        let mut request = self.client.get(self.source_url.clone());
-        if let Some(etag) = cached_etag {
-            request = request.header(IF_NONE_MATCH, etag);
-        }
+        // a conditional request is only useful while the local copy still exists
+        let local_exists = fs::try_exists(&self.path).await.unwrap_or(false);
+        let cached_etag = cached_etag.filter(|_| local_exists);
 
-        let response = request
-            .send()
-            .await
-            .map_err(|source| MappingError::Download {
-                source,
-                url: self.source_url.clone(),
-            })?;
+        let (source_url, outcome) = self.fetch_from_sources(cached_etag.as_ref()).await?;
 
-        if response.status() == StatusCode::NOT_MODIFIED {
-            debug!(
-                path = %self.path.display(),
-                url = %self.source_url,
-                "plexanibridge mappings not modified; skipping refresh"
-            );
-
-            let cache_missing = {
-                let guard = self.cache.read().await;
-                guard.is_none()
-            };
+        let outcome = match outcome {
+            FetchOutcome::NotModified => {
+                debug!(
+                    path = %self.path.display(),
+                    url = %source_url,
+                    "plexanibridge mappings not modified; skipping refresh"
+                );
 
-            if cache_missing {
-                // ensure cache is hydrated so downstream calls can serve requests
-                self.load_mappings().await?;
+                // ensure cache is hydrated so downstream calls can serve requests; this is a
+                // no-op unless the cache is empty or overrides.json changed on disk
+                match self.load_mappings().await {
+                    Ok(_) => return Ok(()),
+                    Err(MappingError::Deserialisation(error)) => {
+                        warn!(
+                            error = %error,
+                            url = %source_url,
+                            "local plexanibridge mappings were corrupt; downloading a fresh copy"
+                        );
+                        self.fetch_mappings(&source_url, None).await?
+                    }
+                    Err(error) => return Err(error),
+                }
             }
-
+            downloaded => downloaded,
+        };
+        let FetchOutcome::Downloaded {
+            etag: new_etag,
+            bytes,
+        } = outcome
+        else {
+            // the source answered 304 to an unconditional request; keep what is on disk
             return Ok(());
-        }
-
-        let response = response
-            .error_for_status()
-            .map_err(|source| MappingError::Download {
-                source,
-                url: self.source_url.clone(),
-            })?;
-
-        let new_etag = response
-            .headers()
-            .get(ETAG)
-            .and_then(|value| value.to_str().ok())
-            .map(|value| value.to_owned());
+        };
 
-        let bytes = response
-            .bytes()
+        // The download goes to the temp file before parsing so the blocking task can take
+        // the bytes and drop them as soon as they're parsed. Peak memory is then the bytes
+        // plus the parsed records, or the records plus the index built from them (which
+        // consumes the records as it goes), instead of all three plus a copy of the bytes.
+        let temp_path = self.path.with_extension("json.tmp");
+        write_synced(&temp_path, &bytes)
             .await
-            .map_err(|source| MappingError::Download {
+            .map_err(|source| MappingError::Write {
                 source,
-                url: self.source_url.clone(),
-            })?
-            .to_vec();
+                path: temp_path.clone(),
+            })?;
 
         // Offload heavy JSON deserialisation and index build to a blocking thread so the
         // async runtime worker threads aren't stalled by CPU work.
-        let index = {
-            let bytes = bytes.clone();
-            task::spawn_blocking(move || {
-                let raw: HashMap<String, RawMappingRecord> = serde_json::from_slice(&bytes)?;
-                Ok::<MappingIndex, MappingError>(Self::build_index(raw))
-            })
-            .await??
+        let overrides_modified = self.overrides_modified().await;
+        let overrides = self.read_overrides().await;
+        let parsed = task::spawn_blocking(move || {
+            let raw: HashMap<String, RawMappingRecord> = serde_json::from_slice(&bytes)?;
+            drop(bytes);
+            Ok::<MappingIndex, MappingError>(Self::build_index(raw, overrides))
+        })
+        .await?;
+        let index = match parsed {
+            Ok(index) => index,
+            Err(error) => {
+                // don't leave an unparseable download behind for the next refresh to trip on
+                let _ = fs::remove_file(&temp_path).await;
+                return Err(error);
+            }
         };
         let series = index.tvdb_to_entries.len();
         let entries = index
@@ -245,40 +521,11 @@ impl PlexAniBridgeMappings {
             .sum::<usize>();
         let index = Arc::new(index);
 
-        let temp_path = self.path.with_extension("json.tmp");
-        fs::write(&temp_path, &bytes)
-            .await
-            .map_err(|source| MappingError::Write {
-                source,
-                path: temp_path.clone(),
-            })?;
-
-        match fs::rename(&temp_path, &self.path).await {
-            Ok(()) => {}
-            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
-                fs::remove_file(&self.path)
-                    .await
-                    .map_err(|source| MappingError::Remove {
-                        source,
-                        path: self.path.clone(),
-                    })?;
-                fs::rename(&temp_path, &self.path)
-                    .await
-                    .map_err(|source| MappingError::Write {
-                        source,
-                        path: self.path.clone(),
-                    })?;
-            }
-            Err(source) => {
-                return Err(MappingError::Write {
-                    source,
-                    path: self.path.clone(),
-                });
-            }
-        }
+        replace_file(&temp_path, &self.path).await?;
+        sync_parent_dir(&self.path).await;
 
         if let Some(ref etag) = new_etag {
-            fs::write(&etag_path, etag.as_bytes().to_vec())
+            fs::write(&etag_path, format!("{etag}\n{source_url}\n"))
                 .await
                 .map_err(|source| MappingError::Write {
                     source,
@@ -310,14 +557,28 @@ impl PlexAniBridgeMappings {
             let mut guard = self.cache.write().await;
             *guard = Some(CachedMappings {
                 modified,
+                overrides_modified,
                 etag: new_etag.clone(),
+                source: Some(source_url.clone()),
                 entries: index.clone(),
             });
         }
+        self.misses.write().await.clear();
+
+        if let Some(ref etag) = new_etag {
+            let key = SnapshotKey {
+                etag: etag.clone(),
+                mappings_modified: modified,
+                mappings_len: metadata.len(),
+                overrides_modified,
+                format: INDEX_FORMAT,
+            };
+            self.write_index_snapshot(key, index.clone()).await;
+        }
 
         debug!(
             path = %self.path.display(),
-            url = %self.source_url,
+            url = %source_url,
             series,
             entries,
             "refreshed plexanibridge mappings"
@@ -326,6 +587,132 @@ impl PlexAniBridgeMappings {
         Ok(())
     }
 
+    /// Tries each configured source in turn, starting with the one that last answered, and
+    /// returns the first response along with the URL that served it.
+    async fn fetch_from_sources(
+        &self,
+        cached_etag: Option<&CachedEtag>,
+    ) -> Result<(Url, FetchOutcome), MappingError> {
+        let preferred = self.preferred_source.load(Ordering::Relaxed);
+        let order = std::iter::once(preferred)
+            .chain((0..self.source_urls.len()).filter(|index| *index != preferred));
+
+        let mut last_error = None;
+        for index in order {
+            let Some(url) = self.source_urls.get(index) else {
+                continue;
+            };
+            // etags are only meaningful to the source that issued them; entries written
+            // before fallback sources existed came from the primary source
+            let etag = cached_etag
+                .filter(|cached| match &cached.source {
+                    Some(source) => source == url,
+                    None => index == 0,
+                })
+                .map(|cached| cached.etag.as_str());
+
+            match self.fetch_mappings(url, etag).await {
+                Ok(outcome) => {
+                    if self.preferred_source.swap(index, Ordering::Relaxed) != index {
+                        info!(url = %url, "switched plexanibridge mapping source");
+                    }
+                    return Ok((url.clone(), outcome));
+                }
+                Err(error) => {
+                    if self.source_urls.len() > 1 {
+                        warn!(
+                            error = %error,
+                            url = %url,
+                            "plexanibridge mapping source failed; trying the next one"
+                        );
+                    }
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        Err(last_error.expect("at least one mapping source is configured"))
+    }
+
+    async fn fetch_mappings(
+        &self,
+        url: &Url,
+        etag: Option<&str>,
+    ) -> Result<FetchOutcome, MappingError> {
+        let download_error = |source| MappingError::Download {
+            source,
+            url: url.clone(),
+        };
+
+        let mut request = self.client.get(url.clone());
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        let response = request.send().await.map_err(download_error)?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(FetchOutcome::NotModified);
+        }
+
+        let response = response.error_for_status().map_err(download_error)?;
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_owned());
+        let bytes = response.bytes().await.map_err(download_error)?;
+
+        Ok(FetchOutcome::Downloaded { etag, bytes })
+    }
+
+    /// Reads the etag recorded next to the local mappings file. The first line holds the
+    /// etag and the second the source URL that served it; older files only have the etag.
+    async fn read_etag(&self, failure: &str) -> Option<CachedEtag> {
+        let etag_path = self.etag_path();
+        match fs::read_to_string(&etag_path).await {
+            Ok(value) => {
+                let mut lines = value.lines().map(str::trim);
+                let etag = lines.next().filter(|line| !line.is_empty())?.to_owned();
+                let source = lines.next().and_then(|line| Url::parse(line).ok());
+                Some(CachedEtag { etag, source })
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => None,
+            Err(error) => {
+                warn!(error = %error, path = %etag_path.display(), "{failure}");
+                None
+            }
+        }
+    }
+
+    /// Moves an unparseable mappings file aside and forgets its etag so the next refresh
+    /// downloads a complete copy instead of receiving `304 Not Modified`.
+    async fn quarantine_mappings(&self) {
+        let quarantine_path = self.path.with_extension("json.corrupt");
+        match fs::rename(&self.path, &quarantine_path).await {
+            Ok(()) => warn!(
+                path = %self.path.display(),
+                quarantine = %quarantine_path.display(),
+                "moved corrupt plexanibridge mappings file aside"
+            ),
+            Err(error) => warn!(
+                error = %error,
+                path = %self.path.display(),
+                "failed to move corrupt plexanibridge mappings file aside"
+            ),
+        }
+
+        if let Err(error) = fs::remove_file(self.etag_path()).await
+            && error.kind() != ErrorKind::NotFound
+        {
+            warn!(error = %error, "failed to remove cached etag for corrupt mappings");
+        }
+
+        let mut guard = self.cache.write().await;
+        if let Some(cache) = guard.as_mut() {
+            cache.etag = None;
+        }
+    }
+
     async fn load_mappings(&self) -> Result<Arc<MappingIndex>, MappingError> {
         let metadata = match fs::metadata(&self.path).await {
             Ok(metadata) => metadata,
@@ -350,68 +737,89 @@ impl PlexAniBridgeMappings {
                 path: self.path.clone(),
             })?;
 
-        let etag_path = self.etag_path();
-        let etag = match fs::read_to_string(&etag_path).await {
-            Ok(value) => {
-                let trimmed = value.trim();
-                if trimmed.is_empty() {
-                    None
-                } else {
-                    Some(trimmed.to_owned())
-                }
-            }
-            Err(error) if error.kind() == ErrorKind::NotFound => None,
-            Err(error) => {
-                warn!(
-                    error = %error,
-                    path = %etag_path.display(),
-                    "failed to read cached etag while loading mappings"
-                );
-                None
-            }
+        let cached_etag = self
+            .read_etag("failed to read cached etag while loading mappings")
+            .await;
+        let (etag, source) = match cached_etag {
+            Some(cached) => (Some(cached.etag), cached.source),
+            None => (None, None),
         };
 
-        {
-            let guard = self.cache.read().await;
-            if let Some(cache) = guard.as_ref()
-                && cache.modified == modified
-            {
-                debug!(
-                    path = %self.path.display(),
-                    "using cached plexanibridge mappings"
-                );
-                return Ok(cache.entries.clone());
-            }
+        let overrides_modified = self.overrides_modified().await;
+
+        if let Some(entries) = self.cached_index(modified, overrides_modified).await {
+            return Ok(entries);
         }
 
-        let contents = fs::read(&self.path)
-            .await
-            .map_err(|source| MappingError::Read {
-                source,
-                path: self.path.clone(),
-            })?;
+        // Only one caller parses the file; concurrent callers wait here and then pick up the
+        // index it stored instead of each parsing their own copy.
+        let _load = self.load_lock.lock().await;
+        if let Some(entries) = self.cached_index(modified, overrides_modified).await {
+            return Ok(entries);
+        }
 
-        let index = task::spawn_blocking(move || {
-            let raw: HashMap<String, RawMappingRecord> = serde_json::from_slice(&contents)?;
-            Ok::<MappingIndex, MappingError>(Self::build_index(raw))
-        })
-        .await??;
+        let snapshot_key = etag.as_ref().map(|etag| SnapshotKey {
+            etag: etag.clone(),
+            mappings_modified: modified,
+            mappings_len: metadata.len(),
+            overrides_modified,
+            format: INDEX_FORMAT,
+        });
+        let snapshot = match &snapshot_key {
+            Some(key) => self.read_index_snapshot(key).await,
+            None => None,
+        };
+
+        let index = match snapshot {
+            Some(index) => index,
+            None => {
+                let contents = fs::read(&self.path)
+                    .await
+                    .map_err(|source| MappingError::Read {
+                        source,
+                        path: self.path.clone(),
+                    })?;
+
+                let overrides = self.read_overrides().await;
+                let index = match task::spawn_blocking(move || {
+                    let raw: HashMap<String, RawMappingRecord> = serde_json::from_slice(&contents)?;
+                    Ok::<MappingIndex, MappingError>(Self::build_index(raw, overrides))
+                })
+                .await?
+                {
+                    Ok(index) => index,
+                    Err(error @ MappingError::Deserialisation(_)) => {
+                        self.quarantine_mappings().await;
+                        return Err(error);
+                    }
+                    Err(error) => return Err(error),
+                };
+                let index = Arc::new(index);
+
+                if let Some(key) = snapshot_key {
+                    self.write_index_snapshot(key, index.clone()).await;
+                }
+                index
+            }
+        };
         let series = index.tvdb_to_entries.len();
         let entries = index
             .tvdb_to_entries
             .values()
             .map(|group| group.len())
             .sum::<usize>();
-        let index = Arc::new(index);
 
         {
             let mut guard = self.cache.write().await;
             *guard = Some(CachedMappings {
                 modified,
+                overrides_modified,
                 etag,
+                source,
                 entries: index.clone(),
             });
         }
+        self.misses.write().await.clear();
 
         debug!(
             path = %self.path.display(),
@@ -423,13 +831,202 @@ impl PlexAniBridgeMappings {
         Ok(index)
     }
 
+    /// The index lookups should use: the file on disk when it loads, otherwise the last index
+    /// that loaded successfully, so a deleted or corrupt file doesn't fail every search.
+    /// Errors only when nothing has ever loaded. Warns on the first failed reload and logs
+    /// repeats at debug until a reload succeeds again.
+    async fn current_index(&self) -> Result<Arc<MappingIndex>, MappingError> {
+        let error = match self.load_mappings().await {
+            Ok(index) => {
+                if self.reload_failing.swap(false, Ordering::Relaxed) {
+                    info!(path = %self.path.display(), "reloaded plexanibridge mappings");
+                }
+                return Ok(index);
+            }
+            Err(error) => error,
+        };
+
+        let guard = self.cache.read().await;
+        match guard.as_ref() {
+            Some(cache) => {
+                if self.reload_failing.swap(true, Ordering::Relaxed) {
+                    debug!(
+                        error = %error,
+                        path = %self.path.display(),
+                        "plexanibridge mappings still fail to reload; serving the last good index"
+                    );
+                } else {
+                    warn!(
+                        error = %error,
+                        path = %self.path.display(),
+                        "failed to reload plexanibridge mappings; serving the last good index"
+                    );
+                }
+                Ok(cache.entries.clone())
+            }
+            None => Err(error),
+        }
+    }
+
+    /// Returns the loaded index if it was built from the given mappings and overrides files.
+    async fn cached_index(
+        &self,
+        modified: SystemTime,
+        overrides_modified: Option<SystemTime>,
+    ) -> Option<Arc<MappingIndex>> {
+        let guard = self.cache.read().await;
+        let cache = guard.as_ref()?;
+        if cache.modified != modified || cache.overrides_modified != overrides_modified {
+            return None;
+        }
+
+        debug!(
+            path = %self.path.display(),
+            "using cached plexanibridge mappings"
+        );
+        Some(cache.entries.clone())
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.path.with_extension("index")
+    }
+
+    /// Loads the postcard-encoded index sidecar when it was built from the same mappings
+    /// file (etag, mtime and length) and overrides file, skipping the JSON parse on restart.
+    async fn read_index_snapshot(&self, key: &SnapshotKey) -> Option<Arc<MappingIndex>> {
+        if !self.index_cache {
+            return None;
+        }
+
+        let index_path = self.index_path();
+        let contents = match fs::read(&index_path).await {
+            Ok(contents) => contents,
+            Err(error) => {
+                if error.kind() != ErrorKind::NotFound {
+                    debug!(
+                        error = %error,
+                        path = %index_path.display(),
+                        "failed to read mapping index cache"
+                    );
+                }
+                return None;
+            }
+        };
+
+        let decoded = task::spawn_blocking(move || {
+            postcard::from_bytes::<(SnapshotKey, MappingIndex)>(&contents)
+        })
+        .await
+        .ok()?;
+
+        match decoded {
+            Ok((cached_key, index)) if cached_key == *key => {
+                debug!(path = %index_path.display(), "loaded plexanibridge index from cache");
+                Some(Arc::new(index))
+            }
+            Ok(_) => {
+                debug!(path = %index_path.display(), "mapping index cache is stale; rebuilding");
+                None
+            }
+            Err(error) => {
+                debug!(
+                    error = %error,
+                    path = %index_path.display(),
+                    "mapping index cache is unreadable; rebuilding"
+                );
+                None
+            }
+        }
+    }
+
+    async fn write_index_snapshot(&self, key: SnapshotKey, index: Arc<MappingIndex>) {
+        if !self.index_cache {
+            return;
+        }
+
+        let encoded =
+            task::spawn_blocking(move || postcard::to_stdvec(&(key, index.as_ref()))).await;
+        let bytes = match encoded {
+            Ok(Ok(bytes)) => bytes,
+            Ok(Err(error)) => {
+                warn!(error = %error, "failed to encode mapping index cache");
+                return;
+            }
+            Err(error) => {
+                warn!(error = %error, "failed to encode mapping index cache");
+                return;
+            }
+        };
+
+        let index_path = self.index_path();
+        let temp_path = index_path.with_extension("index.tmp");
+        let result = match write_synced(&temp_path, &bytes).await {
+            Ok(()) => replace_file(&temp_path, &index_path)
+                .await
+                .map_err(|error| error.to_string()),
+            Err(error) => Err(error.to_string()),
+        };
+        if let Err(error) = result {
+            warn!(error, path = %index_path.display(), "failed to write mapping index cache");
+        }
+    }
+
     fn etag_path(&self) -> PathBuf {
         let mut path = self.path.clone();
         path.set_extension("etag");
         path
     }
 
-    fn build_index(raw: HashMap<String, RawMappingRecord>) -> MappingIndex {
+    async fn overrides_modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.overrides_path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Reads the optional user-maintained `overrides.json`, which uses the same shape as
+    /// the PlexAniBridge mappings file. A missing or invalid file yields no overrides.
+    async fn read_overrides(&self) -> HashMap<String, RawMappingRecord> {
+        let contents = match fs::read(&self.overrides_path).await {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return HashMap::new(),
+            Err(error) => {
+                warn!(
+                    error = %error,
+                    path = %self.overrides_path.display(),
+                    "failed to read mapping overrides; ignoring them"
+                );
+                return HashMap::new();
+            }
+        };
+
+        match serde_json::from_slice::<HashMap<String, RawMappingRecord>>(&contents) {
+            Ok(overrides) => {
+                debug!(
+                    path = %self.overrides_path.display(),
+                    overrides = overrides.len(),
+                    "loaded mapping overrides"
+                );
+                overrides
+            }
+            Err(error) => {
+                warn!(
+                    error = %error,
+                    path = %self.overrides_path.display(),
+                    "failed to parse mapping overrides; ignoring them"
+                );
+                HashMap::new()
+            }
+        }
+    }
+
+    fn build_index(
+        mut raw: HashMap<String, RawMappingRecord>,
+        overrides: HashMap<String, RawMappingRecord>,
+    ) -> MappingIndex {
+        // overrides replace downloaded records wholesale on anilist id collision
+        raw.extend(overrides);
+
         let mut tvdb_index: HashMap<i64, Vec<MappingEntry>> = HashMap::new();
         let mut anilist_index: HashMap<i64, Vec<ReverseMappingEntry>> = HashMap::new();
         let mut tmdb_index: HashMap<i64, i64> = HashMap::new();
@@ -454,10 +1051,16 @@ impl PlexAniBridgeMappings {
                 if tvdb_mappings.is_empty() {
                     trace!(anilist_id, tvdb_id, "skipping mapping with no season data");
                 } else {
+                    let episodes = tvdb_mappings
+                        .iter()
+                        .filter_map(|(key, value)| Some((parse_season_key(key)?, value.as_str()?)))
+                        .flat_map(|(season, value)| parse_episode_ranges(season, value))
+                        .collect();
                     let seasons = tvdb_mappings.into_keys().collect::<Vec<_>>();
                     tvdb_index.entry(tvdb_id).or_default().push(MappingEntry {
                         anilist_id,
                         seasons: seasons.clone(),
+                        episodes,
                     });
                     anilist_index
                         .entry(anilist_id)
@@ -485,8 +1088,17 @@ impl PlexAniBridgeMappings {
         tvdb_id: i64,
         season: u32,
     ) -> Result<Option<i64>, MappingError> {
-        let mappings = self.load_mappings().await?;
-        let season_key = format!("s{season}");
+        let mappings = self.current_index().await?;
+
+        {
+            let guard = self.misses.read().await;
+            if let Some(recorded) = guard.get(&(tvdb_id, season))
+                && recorded.elapsed() < NEGATIVE_CACHE_TTL
+            {
+                trace!(tvdb_id, season, "mapping miss served from negative cache");
+                return Ok(None);
+            }
+        }
 
         if let Some(entries) = mappings.tvdb_to_entries.get(&tvdb_id) {
             debug!(
@@ -497,7 +1109,11 @@ impl PlexAniBridgeMappings {
             );
 
             for entry in entries {
-                if entry.seasons.iter().any(|key| key == &season_key) {
+                if entry
+                    .seasons
+                    .iter()
+                    .any(|key| parse_season_key(key) == Some(season))
+                {
                     debug!(
                         tvdb_id,
                         season,
@@ -516,57 +1132,135 @@ impl PlexAniBridgeMappings {
             "no season-specific mapping found in local mappings file"
         );
 
+        let mut guard = self.misses.write().await;
+        guard.retain(|_, recorded| recorded.elapsed() < NEGATIVE_CACHE_TTL);
+        guard.insert((tvdb_id, season), Instant::now());
+
+        Ok(None)
+    }
+
+    /// Finds the AniList entry and tvdb season holding an absolute episode. The episode
+    /// ranges of every entry for the series are laid end to end in season order, skipping
+    /// specials; a run open to the end of its season only counts when nothing follows it,
+    /// since its length is unknown.
+    pub async fn resolve_by_absolute_episode(
+        &self,
+        tvdb_id: i64,
+        episode: u32,
+    ) -> Result<Option<(i64, u32)>, MappingError> {
+        let mappings = self.current_index().await?;
+        let Some(entries) = mappings.tvdb_to_entries.get(&tvdb_id) else {
+            debug!(tvdb_id, episode, "no entries found for tvdb id");
+            return Ok(None);
+        };
+
+        let mut ranges = entries
+            .iter()
+            .flat_map(|entry| {
+                entry
+                    .episodes
+                    .iter()
+                    .map(|range| (*range, entry.anilist_id))
+            })
+            .filter(|(range, _)| range.season > 0)
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|(range, _)| (range.season, range.first));
+
+        let mut preceding = 0u32;
+        for (index, (range, anilist_id)) in ranges.iter().enumerate() {
+            let Some(last) = range.last else {
+                if index + 1 < ranges.len() {
+                    debug!(
+                        tvdb_id,
+                        episode,
+                        season = range.season,
+                        "open episode range ends the count for absolute episode"
+                    );
+                    return Ok(None);
+                }
+                if episode > preceding {
+                    debug!(
+                        tvdb_id,
+                        episode,
+                        anilist_id,
+                        season = range.season,
+                        "matched absolute episode to open range"
+                    );
+                    return Ok(Some((*anilist_id, range.season)));
+                }
+                break;
+            };
+
+            let length = last.saturating_sub(range.first) + 1;
+            if episode <= preceding + length {
+                debug!(
+                    tvdb_id,
+                    episode,
+                    anilist_id,
+                    season = range.season,
+                    "matched absolute episode to episode range"
+                );
+                return Ok(Some((*anilist_id, range.season)));
+            }
+            preceding += length;
+        }
+
+        debug!(tvdb_id, episode, "no episode range covers absolute episode");
         Ok(None)
     }
 
     pub async fn resolve_anilist_id_for_tvdb(
         &self,
         tvdb_id: i64,
-    ) -> Result<Option<i64>, MappingError> {
-        let mappings = self.load_mappings().await?;
+        strategy: SeasonStrategy,
+    ) -> Result<Vec<i64>, MappingError> {
+        let mappings = self.current_index().await?;
         let Some(entries) = mappings.tvdb_to_entries.get(&tvdb_id) else {
             debug!(tvdb_id, "no entries found for tvdb id");
-            return Ok(None);
+            return Ok(Vec::new());
         };
 
-        let mut best: Option<(i64, u32)> = None;
-        for entry in entries {
-            let mut seasons: Vec<u32> = entry
+        let selected = strategy.select(entries.iter().map(|entry| {
+            let seasons = entry
                 .seasons
                 .iter()
                 .filter_map(|key| parse_season_key(key))
                 .collect();
+            (entry.anilist_id, seasons)
+        }));
 
-            let season = if seasons.is_empty() {
-                u32::MAX
-            } else {
-                seasons.sort_unstable();
-                seasons[0]
-            };
-
-            match best {
-                Some((_, best_season)) if season >= best_season => {}
-                _ => best = Some((entry.anilist_id, season)),
+        if !selected.is_empty() {
+            for (anilist_id, season) in &selected {
+                debug!(
+                    tvdb_id,
+                    anilist_id, season, "selected mapping for tv search"
+                );
             }
+            return Ok(selected
+                .into_iter()
+                .map(|(anilist_id, _)| anilist_id)
+                .collect());
         }
 
-        if let Some((anilist_id, season)) = best {
+        // entries without any season key are only used when nothing better exists
+        if let Some(entry) = entries.first() {
             debug!(
                 tvdb_id,
-                anilist_id, season, "selected mapping for tv search"
+                anilist_id = entry.anilist_id,
+                "selected seasonless mapping"
             );
-            return Ok(Some(anilist_id));
+            return Ok(vec![entry.anilist_id]);
         }
 
         debug!(tvdb_id, "failed to select mapping for movie search");
-        Ok(None)
+        Ok(Vec::new())
     }
 
     pub async fn resolve_anilist_id_for_tmdb(
         &self,
         tmdb_id: i64,
     ) -> Result<Option<i64>, MappingError> {
-        let mappings = self.load_mappings().await?;
+        let mappings = self.current_index().await?;
         if let Some(anilist_id) = mappings.tmdb_to_anilist.get(&tmdb_id) {
             debug!(tmdb_id, anilist_id, "resolved tmdb mapping");
             Ok(Some(*anilist_id))
@@ -577,15 +1271,87 @@ impl PlexAniBridgeMappings {
     }
 
     pub async fn resolve_tmdb_id(&self, anilist_id: i64) -> Result<Option<i64>, MappingError> {
-        let mappings = self.load_mappings().await?;
+        let mappings = self.current_index().await?;
         Ok(mappings.anilist_to_tmdb.get(&anilist_id).copied())
     }
 
+    /// Diagnostic counterpart to [`Self::resolve_anilist_id`] that explains a miss instead
+    /// of caching it. The mappings only carry tmdb ids for movies, so imdb ids can't be
+    /// followed.
+    pub async fn resolve_nearby(
+        &self,
+        tvdb_id: i64,
+        season: Option<u32>,
+        tmdb_id: Option<i64>,
+        anilist_id: Option<i64>,
+    ) -> Result<NearbyMappings, MappingError> {
+        let mappings = self.current_index().await?;
+        let entries: Vec<NearbyEntry> = mappings
+            .tvdb_to_entries
+            .get(&tvdb_id)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .map(|entry| NearbyEntry {
+                        anilist_id: entry.anilist_id,
+                        seasons: entry.seasons.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let season_match = season.and_then(|season| {
+            entries
+                .iter()
+                .find(|entry| {
+                    entry
+                        .seasons
+                        .iter()
+                        .any(|key| parse_season_key(key) == Some(season))
+                })
+                .map(|entry| entry.anilist_id)
+        });
+
+        let mut alternatives = Vec::new();
+        if entries.is_empty() {
+            let reachable = [
+                (
+                    "tmdb",
+                    tmdb_id.and_then(|id| mappings.tmdb_to_anilist.get(&id).copied()),
+                ),
+                ("anilist", anilist_id),
+            ];
+            for (via, anilist_id) in reachable {
+                let Some(anilist_id) = anilist_id else {
+                    continue;
+                };
+                let tvdb_ids = mappings
+                    .anilist_to_entries
+                    .get(&anilist_id)
+                    .map(|entries| entries.iter().map(|entry| entry.tvdb_id).collect())
+                    .unwrap_or_default();
+                alternatives.push(AlternativeMapping {
+                    via,
+                    anilist_id,
+                    tvdb_ids,
+                });
+            }
+        }
+
+        Ok(NearbyMappings {
+            tvdb_id,
+            tvdb_known: !entries.is_empty(),
+            season_match,
+            entries,
+            alternatives,
+        })
+    }
+
     pub async fn resolve_tvdb_mappings(
         &self,
         anilist_id: i64,
     ) -> Result<Vec<TvdbMapping>, MappingError> {
-        let mappings = self.load_mappings().await?;
+        let mappings = self.current_index().await?;
 
         let result = mappings
             .anilist_to_entries
@@ -605,15 +1371,109 @@ impl PlexAniBridgeMappings {
     }
 }
 
-pub(crate) fn parse_season_key(key: &str) -> Option<u32> {
-    if !key.starts_with('s') {
-        return None;
+/// Writes `contents` to `path` and flushes it to disk so a crash after the following
+/// rename can't leave a truncated file behind.
+async fn write_synced(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = fs::File::create(path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await
+}
+
+/// Flushes the directory entry for `path` so a completed rename survives power loss.
+/// Best effort: not every platform allows opening a directory for syncing.
+async fn sync_parent_dir(path: &Path) {
+    let Some(parent) = path.parent() else {
+        return;
+    };
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    if let Ok(dir) = fs::File::open(parent).await
+        && let Err(error) = dir.sync_all().await
+    {
+        trace!(error = %error, path = %parent.display(), "failed to sync directory");
+    }
+}
+
+/// Moves `temp_path` over `target`. The temp file lives next to the target so a plain
+/// rename normally suffices, but bind mounts and overlay filesystems can still report
+/// `EXDEV`; in that case the contents are copied, flushed to disk and the temp removed.
+async fn replace_file(temp_path: &Path, target: &Path) -> Result<(), MappingError> {
+    let renamed = fs::rename(temp_path, target).await;
+    settle_rename(renamed, temp_path, target).await
+}
+
+/// Finishes [`replace_file`] once its first rename attempt returned `renamed`.
+async fn settle_rename(
+    renamed: std::io::Result<()>,
+    temp_path: &Path,
+    target: &Path,
+) -> Result<(), MappingError> {
+    match renamed {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            fs::remove_file(target)
+                .await
+                .map_err(|source| MappingError::Remove {
+                    source,
+                    path: target.to_path_buf(),
+                })?;
+            fs::rename(temp_path, target)
+                .await
+                .map_err(|source| MappingError::Write {
+                    source,
+                    path: target.to_path_buf(),
+                })
+        }
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => {
+            debug!(
+                temp = %temp_path.display(),
+                path = %target.display(),
+                "rename crossed devices; falling back to copy"
+            );
+            let write_error = |source| MappingError::Write {
+                source,
+                path: target.to_path_buf(),
+            };
+            fs::copy(temp_path, target).await.map_err(write_error)?;
+            fs::File::open(target)
+                .await
+                .map_err(write_error)?
+                .sync_all()
+                .await
+                .map_err(write_error)?;
+            fs::remove_file(temp_path)
+                .await
+                .map_err(|source| MappingError::Remove {
+                    source,
+                    path: temp_path.to_path_buf(),
+                })
+        }
+        Err(source) => Err(MappingError::Write {
+            source,
+            path: target.to_path_buf(),
+        }),
     }
+}
+
+/// Spreads `base` by up to `jitter` (a fraction, e.g. `0.1` for ±10%) so instances started
+/// together don't hit the mapping source on the same boundary.
+fn jittered(base: Duration, jitter: f64, rng: &mut SmallRng) -> Duration {
+    if jitter <= 0.0 {
+        return base;
+    }
+    let factor = 1.0 + rng.random_range(-jitter..=jitter);
+    base.mul_f64(factor)
+}
 
-    let digits: String = key[1..]
-        .chars()
-        .take_while(|ch| ch.is_ascii_digit())
-        .collect();
+/// Parses a `tvdb_mappings` key such as `s1` into its season number. The prefix is matched
+/// case-insensitively and leading zeros are ignored, so `S1`, `s01` and `s1` are all season 1.
+pub(crate) fn parse_season_key(key: &str) -> Option<u32> {
+    let rest = key.trim().strip_prefix(['s', 'S'])?;
+
+    let digits: String = rest.chars().take_while(|ch| ch.is_ascii_digit()).collect();
     if digits.is_empty() {
         return None;
     }
@@ -621,6 +1481,45 @@ pub(crate) fn parse_season_key(key: &str) -> Option<u32> {
     digits.parse().ok()
 }
 
+/// Parses a `tvdb_mappings` value into its episode runs. Values are comma-separated
+/// segments such as `e1-e12`, `e13-`, `-e5` or `e7`, each optionally followed by a
+/// `|ratio`; an empty value maps the whole season. Malformed segments are skipped.
+fn parse_episode_ranges(season: u32, value: &str) -> Vec<EpisodeRange> {
+    let value = value.trim();
+    if value.is_empty() {
+        return vec![EpisodeRange {
+            season,
+            first: 1,
+            last: None,
+        }];
+    }
+
+    let episode =
+        |value: &str| -> Option<u32> { value.trim().strip_prefix(['e', 'E'])?.parse().ok() };
+
+    value
+        .split(',')
+        .filter_map(|segment| {
+            let segment = segment.split('|').next()?.trim();
+            let (first, last) = match segment.split_once('-') {
+                Some((first, "")) => (episode(first)?, None),
+                Some(("", last)) => (1, Some(episode(last)?)),
+                Some((first, last)) => (episode(first)?, Some(episode(last)?)),
+                None => {
+                    let episode = episode(segment)?;
+                    (episode, Some(episode))
+                }
+            };
+            last.is_none_or(|last| last >= first)
+                .then_some(EpisodeRange {
+                    season,
+                    first,
+                    last,
+                })
+        })
+        .collect()
+}
+
 #[derive(Debug, Error)]
 pub enum MappingError {
     #[error("failed to download plexanibridge mappings from {url}")]
@@ -658,3 +1557,188 @@ pub enum MappingError {
     #[error("background task failed")]
     TaskJoin(#[from] tokio::task::JoinError),
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::test_support;
+
+    const MAPPINGS: &str = r#"{"21": {"tvdb_id": 81797, "tvdb_mappings": {"s1": ""}}}"#;
+
+    async fn bootstrap(cache: &Path, mappings: &str) -> PlexAniBridgeMappings {
+        bootstrap_from(cache, mappings, "http://127.0.0.1:9/mappings.json").await
+    }
+
+    /// Bootstraps from `mappings` on disk, downloading from `source_url` if they don't load.
+    async fn bootstrap_from(
+        cache: &Path,
+        mappings: &str,
+        source_url: &str,
+    ) -> PlexAniBridgeMappings {
+        std::fs::write(cache.join("mappings.json"), mappings).unwrap();
+        let cache_path = cache.to_str().unwrap();
+        let config = AppConfig::for_tests(&[
+            ("SEADEXER_DATA_PATH", cache_path),
+            ("SEADEXER_MAPPING_REFRESH_SECS", "0"),
+            ("SEADEXER_MAPPING_SOURCE_URL", source_url),
+        ]);
+        PlexAniBridgeMappings::bootstrap(&config).await.unwrap()
+    }
+
+    #[test]
+    fn episode_ranges_parse_every_segment_form() {
+        let range = |first, last| EpisodeRange {
+            season: 2,
+            first,
+            last,
+        };
+        assert_eq!(parse_episode_ranges(2, ""), vec![range(1, None)]);
+        assert_eq!(
+            parse_episode_ranges(2, "e1-e12|2, e13-, -e5, E7, e9-e3, x1"),
+            vec![
+                range(1, Some(12)),
+                range(13, None),
+                range(1, Some(5)),
+                range(7, Some(7))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn absolute_episodes_resolve_through_episode_ranges() {
+        let cache = tempfile::tempdir().unwrap();
+        let mappings = bootstrap(
+            cache.path(),
+            r#"{
+                "21": {"tvdb_id": 100, "tvdb_mappings": {"s0": "e1", "s1": "e1-e12"}},
+                "22": {"tvdb_id": 100, "tvdb_mappings": {"s1": "e13-e24", "s2": "e1-e10"}},
+                "23": {"tvdb_id": 100, "tvdb_mappings": {"s3": ""}},
+                "31": {"tvdb_id": 200, "tvdb_mappings": {"s1": "", "s2": "e1-e12"}}
+            }"#,
+        )
+        .await;
+
+        for (episode, expected) in [
+            (1, Some((21, 1))),
+            (12, Some((21, 1))),
+            (13, Some((22, 1))),
+            (25, Some((22, 2))),
+            (34, Some((22, 2))),
+            (35, Some((23, 3))),
+            (500, Some((23, 3))),
+        ] {
+            assert_eq!(
+                mappings
+                    .resolve_by_absolute_episode(100, episode)
+                    .await
+                    .unwrap(),
+                expected,
+                "{episode}"
+            );
+        }
+        assert_eq!(
+            mappings.resolve_by_absolute_episode(200, 1).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            mappings.resolve_by_absolute_episode(300, 1).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_reload_keeps_previous_index() {
+        let cache = tempfile::tempdir().unwrap();
+        let mappings = bootstrap(cache.path(), MAPPINGS).await;
+        assert_eq!(
+            mappings.resolve_anilist_id(81797, 1).await.unwrap(),
+            Some(21)
+        );
+
+        let path = cache.path().join("mappings.json");
+        std::fs::write(&path, "not json").unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(
+                mappings.resolve_anilist_id(81797, 1).await.unwrap(),
+                Some(21)
+            );
+            assert!(mappings.reload_failing.load(Ordering::Relaxed));
+        }
+    }
+
+    #[test]
+    fn season_keys_ignore_case_and_leading_zeros() {
+        for key in ["s1", "S1", "s01", "S001"] {
+            assert_eq!(parse_season_key(key), Some(1), "{key}");
+        }
+        assert_eq!(parse_season_key("s10"), Some(10));
+        assert_eq!(parse_season_key("s0"), Some(0));
+        assert_eq!(parse_season_key("e1"), None);
+        assert_eq!(parse_season_key("s"), None);
+    }
+
+    #[tokio::test]
+    async fn uppercase_and_padded_season_keys_resolve() {
+        let cache = tempfile::tempdir().unwrap();
+        let mappings = bootstrap(
+            cache.path(),
+            r#"{
+                "21": {"tvdb_id": 81797, "tvdb_mappings": {"S01": ""}},
+                "22": {"tvdb_id": 81797, "tvdb_mappings": {"s02": ""}}
+            }"#,
+        )
+        .await;
+
+        assert_eq!(
+            mappings.resolve_anilist_id(81797, 1).await.unwrap(),
+            Some(21)
+        );
+        assert_eq!(
+            mappings.resolve_anilist_id(81797, 2).await.unwrap(),
+            Some(22)
+        );
+    }
+
+    #[tokio::test]
+    async fn cross_device_rename_falls_back_to_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_path = dir.path().join("mappings.json.tmp");
+        let target = dir.path().join("mappings.json");
+        std::fs::write(&temp_path, "new").unwrap();
+        std::fs::write(&target, "old").unwrap();
+
+        let renamed = Err(std::io::Error::from(ErrorKind::CrossesDevices));
+        settle_rename(renamed, &temp_path, &target).await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "new");
+        assert!(!temp_path.exists());
+    }
+
+    #[tokio::test]
+    async fn corrupt_mappings_are_quarantined_and_downloaded_again() {
+        let source = test_support::serve(
+            axum::Router::new().route("/mappings.json", axum::routing::get(|| async { MAPPINGS })),
+        )
+        .await;
+        let source_url = source.join("mappings.json").unwrap();
+
+        let cache = tempfile::tempdir().unwrap();
+        let mappings = bootstrap_from(cache.path(), "{\"21\": {", source_url.as_str()).await;
+
+        assert_eq!(
+            mappings.resolve_anilist_id(81797, 1).await.unwrap(),
+            Some(21)
+        );
+        let quarantined = cache.path().join("mappings.json.corrupt");
+        assert_eq!(std::fs::read_to_string(quarantined).unwrap(), "{\"21\": {");
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use reqwest::{Client, Url};
+use thiserror::Error;
+use tokio::sync::RwLock;
+use tracing::debug;
+
+use crate::config::OutboundConfig;
+
+const DEFAULT_BASE_URL: &str = "https://nyaa.si/";
+const PEER_CACHE_TTL: Duration = Duration::from_secs(1_800);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCounts {
+    pub seeders: u32,
+    pub leechers: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct NyaaClient {
+    http: Client,
+    base_url: Url,
+    cache: Arc<RwLock<HashMap<String, (Instant, PeerCounts)>>>,
+}
+
+impl NyaaClient {
+    pub fn new(
+        base_url: Url,
+        timeout: Duration,
+        outbound: &OutboundConfig,
+    ) -> anyhow::Result<Self> {
+        let http = outbound.client_builder(timeout)?.build()?;
+
+        Ok(Self {
+            http,
+            base_url,
+            cache: Arc::new(RwLock::new(HashMap::new())),
+        })
+    }
+
+    pub async fn peer_counts(&self, nyaa_id: &str) -> Result<PeerCounts, NyaaError> {
+        {
+            let guard = self.cache.read().await;
+            if let Some((fetched_at, counts)) = guard.get(nyaa_id)
+                && fetched_at.elapsed() < PEER_CACHE_TTL
+            {
+                debug!(nyaa_id, "using cached nyaa peer counts");
+                return Ok(*counts);
+            }
+        }
+
+        let url = self
+            .base_url
+            .join(&format!("view/{nyaa_id}"))
+            .map_err(NyaaError::Url)?;
+
+        debug!(nyaa_id, url = %url, "scraping nyaa view page for peer counts");
+
+        let body = self
+            .http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let counts = PeerCounts {
+            seeders: scrape_count(&body, "Seeders:").ok_or(NyaaError::MissingPeers {
+                id: nyaa_id.to_string(),
+            })?,
+            leechers: scrape_count(&body, "Leechers:").unwrap_or(0),
+        };
+
+        let mut guard = self.cache.write().await;
+        guard.retain(|_, (fetched_at, _)| fetched_at.elapsed() < PEER_CACHE_TTL);
+        guard.insert(nyaa_id.to_string(), (Instant::now(), counts));
+
+        Ok(counts)
+    }
+}
+
+pub fn default_base_url() -> Url {
+    Url::parse(DEFAULT_BASE_URL).expect("default nyaa base url is valid")
+}
+
+/// Reads the first number rendered as element text after `label` on a nyaa view page.
+fn scrape_count(html: &str, label: &str) -> Option<u32> {
+    let start = html.find(label)? + label.len();
+    let mut rest = &html[start..];
+
+    while let Some(open) = rest.find('>') {
+        rest = &rest[open + 1..];
+        let text = rest.split('<').next().unwrap_or("").trim();
+        if !text.is_empty() {
+            return text.parse().ok();
+        }
+    }
+
+    None
+}
+
+#[derive(Debug, Error)]
+pub enum NyaaError {
+    #[error("failed to build nyaa request url")]
+    Url(#[from] url::ParseError),
+    #[error("http error when querying nyaa")]
+    Http(#[from] reqwest::Error),
+    #[error("nyaa view page for {id} did not contain peer counts")]
+    MissingPeers { id: String },
+}
@@ -1,22 +1,26 @@
 use std::{
     collections::{HashMap, HashSet},
-    io::ErrorKind,
+    io::{ErrorKind, Write},
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
 
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 use tokio::task;
-use tracing::debug;
+use tracing::{debug, warn};
 use url::Url;
 
+use crate::config::{OutboundConfig, RadarrConfig};
+use crate::title_cache::{CachedTitle, TitleCache, decode_cache, encode_cache};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RadarrMovie {
     pub title: String,
+    /// Release year, `0` when Radarr doesn't know it.
     pub year: u32,
 }
 
@@ -25,30 +29,41 @@ pub struct RadarrClient {
     http: Client,
     base_url: Url,
     api_key: String,
-    cache: Arc<RwLock<HashMap<i64, RadarrMovie>>>,
+    api_key_in_query: bool,
+    cache: Arc<RwLock<TitleCache<CachedTitle>>>,
     cache_path: PathBuf,
+    /// Ids seeded by the last prewarm; pruning leaves them alone.
+    prewarmed: Arc<RwLock<HashSet<i64>>>,
+    /// Serialises cache writes, which share one temp file.
+    persist_lock: Arc<Mutex<()>>,
 }
 
+/// How often monitored movie titles are re-read when `SEADEXER_PREWARM` is enabled.
+const PREWARM_INTERVAL: Duration = Duration::from_secs(900);
+
 impl RadarrClient {
     pub fn new(
-        base_url: Url,
-        api_key: String,
-        timeout: Duration,
+        config: &RadarrConfig,
         cache_path: PathBuf,
+        cache_max: usize,
+        outbound: &OutboundConfig,
     ) -> anyhow::Result<Self> {
-        let http = Client::builder()
-            .timeout(timeout)
-            .user_agent(format!("seadexerr/{}", env!("CARGO_PKG_VERSION")))
+        let http = outbound
+            .client_builder(config.timeout)?
+            .danger_accept_invalid_certs(config.insecure)
             .build()?;
 
-        let cache = load_cache(&cache_path)?;
+        let cache = TitleCache::from_entries(load_cache(&cache_path)?, cache_max);
 
         Ok(Self {
             http,
-            base_url,
-            api_key,
+            base_url: config.url.clone(),
+            api_key: config.api_key.clone(),
+            api_key_in_query: config.api_key_in_query,
             cache: Arc::new(RwLock::new(cache)),
             cache_path,
+            prewarmed: Arc::new(RwLock::new(HashSet::new())),
+            persist_lock: Arc::new(Mutex::new(())),
         })
     }
 
@@ -70,28 +85,27 @@ impl RadarrClient {
 
         debug!(tmdb_id, url = %url, "requesting Radarr movie lookup");
 
-        let response = self
-            .http
-            .get(url)
-            .header("X-Api-Key", &self.api_key)
-            .send()
-            .await?
-            .error_for_status()?;
+        let response = self.authorised_get(url).send().await?.error_for_status()?;
 
-        let payload: MovieLookupEntry = response.json().await?;
-
-        let Some(title) = payload.title else {
+        let payload: MovieLookupResponse = response.json().await?;
+        let Some(payload) = payload.into_entry(tmdb_id) else {
             return Err(RadarrError::NotFound { tmdb_id });
         };
 
-        let Some(year) = payload.year else {
-            debug!(tmdb_id, "skipping Radarr movie lookup due to missing year");
+        let Some(title) = payload.title else {
             return Err(RadarrError::NotFound { tmdb_id });
         };
 
+        if payload.year.is_none() {
+            debug!(
+                tmdb_id,
+                "Radarr movie lookup has no year; using the title alone"
+            );
+        }
+
         let movie = RadarrMovie {
             title,
-            year,
+            year: payload.year.unwrap_or(0),
         };
 
         self.store_movie(tmdb_id, &movie).await?;
@@ -99,71 +113,144 @@ impl RadarrClient {
         Ok(movie)
     }
 
-    pub async fn retain_titles(&self, keep: &HashSet<i64>) -> Result<(), RadarrError> {
-        if keep.is_empty() {
+    /// Seeds the title cache with Radarr's monitored movies at startup and then on a timer,
+    /// so the first feeds after a restart don't look every movie up individually.
+    pub fn spawn_prewarm(&self) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match this.prewarm().await {
+                    Ok(movies) => debug!(movies, "prewarmed Radarr titles"),
+                    Err(error) => warn!(error = %error, "failed to prewarm Radarr titles"),
+                }
+                tokio::time::sleep(PREWARM_INTERVAL).await;
+            }
+        });
+    }
+
+    async fn prewarm(&self) -> Result<usize, RadarrError> {
+        let url = self
+            .base_url
+            .join("api/v3/movie")
+            .map_err(RadarrError::Url)?;
+        let response = self.authorised_get(url).send().await?.error_for_status()?;
+        let payload: Vec<LibraryMovieEntry> = response.json().await?;
+
+        let mut prewarmed = HashSet::new();
+        {
             let mut guard = self.cache.write().await;
-            if guard.is_empty() {
-                return Ok(());
+            for entry in payload.into_iter().filter(|entry| entry.monitored) {
+                let (Some(tmdb_id), Some(title)) = (entry.tmdb_id, entry.title) else {
+                    continue;
+                };
+                guard.insert(tmdb_id, CachedTitle::new(title, entry.year));
+                prewarmed.insert(tmdb_id);
             }
-            guard.clear();
-            drop(guard);
-            return self.persist_cache().await;
         }
+        let movies = prewarmed.len();
+        *self.prewarmed.write().await = prewarmed;
+        self.persist_cache().await?;
 
+        Ok(movies)
+    }
+
+    /// Prunes the title cache down to `keep`, sparing titles seeded by the prewarm so the
+    /// next feed doesn't throw away what was loaded ahead of it.
+    pub async fn retain_titles(&self, keep: &HashSet<i64>) -> Result<(), RadarrError> {
+        let prewarmed = self.prewarmed.read().await;
         let mut guard = self.cache.write().await;
         let original_len = guard.len();
-        guard.retain(|tmdb_id, _| keep.contains(tmdb_id));
+        guard.retain(|tmdb_id| keep.contains(tmdb_id) || prewarmed.contains(tmdb_id));
 
         if guard.len() == original_len {
             return Ok(());
         }
 
         drop(guard);
+        drop(prewarmed);
         self.persist_cache().await
     }
 
+    /// Builds a GET request carrying the API key, either as the `X-Api-Key` header or,
+    /// for proxies that strip custom headers, as an `apikey` query parameter.
+    fn authorised_get(&self, mut url: Url) -> RequestBuilder {
+        if self.api_key_in_query {
+            url.query_pairs_mut().append_pair("apikey", &self.api_key);
+            self.http.get(url)
+        } else {
+            self.http.get(url).header("X-Api-Key", &self.api_key)
+        }
+    }
+
     async fn cached_movie(&self, tmdb_id: i64) -> Option<RadarrMovie> {
-        let guard = self.cache.read().await;
-        guard.get(&tmdb_id).cloned()
+        let mut guard = self.cache.write().await;
+        guard.get(tmdb_id).map(|cached| RadarrMovie {
+            title: cached.title,
+            year: cached.year.unwrap_or(0),
+        })
     }
 
     async fn store_movie(&self, tmdb_id: i64, movie: &RadarrMovie) -> Result<(), RadarrError> {
         {
             let mut guard = self.cache.write().await;
-            guard.insert(tmdb_id, movie.clone());
+            guard.insert(
+                tmdb_id,
+                CachedTitle::new(
+                    movie.title.clone(),
+                    Some(movie.year).filter(|year| *year > 0),
+                ),
+            );
         }
         self.persist_cache().await
     }
 
     async fn persist_cache(&self) -> Result<(), RadarrError> {
+        // held until the rename, so the snapshot written last is also the newest one
+        let _persist = self.persist_lock.lock().await;
         // Clone snapshot while holding the lock then offload CPU + IO to blocking thread.
         let snapshot = {
             let guard = self.cache.read().await;
-            guard.clone()
+            guard.entries().clone()
         };
 
         let path = self.cache_path.clone();
 
-        let result = task::spawn_blocking(move || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-            let json = serde_json::to_vec_pretty(&snapshot)?;
-
-            if let Some(parent) = path.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-
-            std::fs::write(&path, json)?;
-
-            Ok(())
-        })
+        let result = task::spawn_blocking(
+            move || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+                let json = encode_cache(&snapshot)?;
+
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+
+                // Write to a temp file and fsync it before renaming so a crash can't leave a
+                // truncated cache behind.
+                let temp_path = path.with_extension("json.tmp");
+                {
+                    let mut file = std::fs::File::create(&temp_path)?;
+                    file.write_all(&json)?;
+                    file.sync_all()?;
+                }
+                std::fs::rename(&temp_path, &path)?;
+
+                if let Some(parent) = path.parent()
+                    && let Ok(dir) = std::fs::File::open(parent)
+                {
+                    let _ = dir.sync_all();
+                }
+
+                Ok(())
+            },
+        )
         .await
         .map_err(|source| RadarrError::CacheWrite {
-            source: std::io::Error::new(std::io::ErrorKind::Other, format!("join error: {source}")),
+            source: std::io::Error::other(format!("join error: {source}")),
             path: self.cache_path.clone(),
         })?;
 
         if let Err(_err) = result {
             return Err(RadarrError::CacheWrite {
-                source: std::io::Error::new(std::io::ErrorKind::Other, "failed to persist cache"),
+                source: std::io::Error::other("failed to persist cache"),
                 path: self.cache_path.clone(),
             });
         }
@@ -174,13 +261,40 @@ impl RadarrClient {
 
 #[derive(Debug, Deserialize)]
 struct MovieLookupEntry {
+    #[serde(default, rename = "tmdbId")]
+    tmdb_id: Option<i64>,
     #[serde(default)]
     title: Option<String>,
     #[serde(default)]
     year: Option<u32>,
 }
 
-fn load_cache(path: &Path) -> Result<HashMap<i64, RadarrMovie>, RadarrError> {
+/// Radarr answers `movie/lookup/tmdb` with a bare object, but some versions and proxies
+/// wrap it in an array.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MovieLookupResponse {
+    Single(MovieLookupEntry),
+    Multiple(Vec<MovieLookupEntry>),
+}
+
+impl MovieLookupResponse {
+    /// Prefers the candidate whose `tmdbId` matches, falling back to the first one.
+    fn into_entry(self, tmdb_id: i64) -> Option<MovieLookupEntry> {
+        match self {
+            MovieLookupResponse::Single(entry) => Some(entry),
+            MovieLookupResponse::Multiple(entries) => {
+                let position = entries
+                    .iter()
+                    .position(|entry| entry.tmdb_id == Some(tmdb_id))
+                    .unwrap_or(0);
+                entries.into_iter().nth(position)
+            }
+        }
+    }
+}
+
+fn load_cache(path: &Path) -> Result<HashMap<i64, CachedTitle>, RadarrError> {
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent).map_err(|source| RadarrError::CacheDir {
             source,
@@ -203,13 +317,39 @@ fn load_cache(path: &Path) -> Result<HashMap<i64, RadarrMovie>, RadarrError> {
         return Ok(HashMap::new());
     }
 
-    let data: HashMap<i64, RadarrMovie> =
-        serde_json::from_slice(&bytes).map_err(|source| RadarrError::CacheParse {
-            source,
-            path: path.to_path_buf(),
-        })?;
+    // releases before the versioned format stored `{title, year}` objects
+    match decode_cache(&bytes, |movie: RadarrMovie| {
+        CachedTitle::new(movie.title, Some(movie.year).filter(|year| *year > 0))
+    }) {
+        Ok(data) => Ok(data),
+        Err(error) => {
+            // A corrupt cache only costs a few extra lookups, so start empty rather than
+            // refusing to start.
+            let quarantine_path = path.with_extension("json.corrupt");
+            let moved = std::fs::rename(path, &quarantine_path).is_ok();
+            warn!(
+                error = %error,
+                path = %path.display(),
+                quarantine = %quarantine_path.display(),
+                moved,
+                "cached Radarr titles were corrupt; starting with an empty cache"
+            );
+            Ok(HashMap::new())
+        }
+    }
+}
 
-    Ok(data)
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LibraryMovieEntry {
+    #[serde(default)]
+    tmdb_id: Option<i64>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    year: Option<u32>,
+    #[serde(default)]
+    monitored: bool,
 }
 
 #[derive(Debug, Error)]
@@ -217,7 +357,7 @@ pub enum RadarrError {
     #[error("failed to build Radarr request url")]
     Url(#[from] url::ParseError),
     #[error("http error when querying Radarr api")]
-    Http(#[from] reqwest::Error),
+    Http(#[source] reqwest::Error),
     #[error("no Radarr movie title found for tmdb {tmdb_id}")]
     NotFound { tmdb_id: i64 },
     #[error("failed to read cached Radarr titles at {path}")]
@@ -232,12 +372,6 @@ pub enum RadarrError {
         source: std::io::Error,
         path: PathBuf,
     },
-    #[error("failed to parse cached Radarr titles at {path}")]
-    CacheParse {
-        #[source]
-        source: serde_json::Error,
-        path: PathBuf,
-    },
     #[error("failed to serialise cached Radarr titles")]
     CacheSerialise(#[from] serde_json::Error),
     #[error("failed to create cache directory at {path}")]
@@ -247,3 +381,71 @@ pub enum RadarrError {
         path: PathBuf,
     },
 }
+
+impl From<reqwest::Error> for RadarrError {
+    /// Drops the request URL, which carries the API key with `RADARR_APIKEY_IN_QUERY`.
+    fn from(error: reqwest::Error) -> Self {
+        Self::Http(error.without_url())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn http_errors_leave_out_the_query_api_key() {
+        let upstream =
+            test_support::serve(Router::new().fallback(|| async { StatusCode::FORBIDDEN })).await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = AppConfig::for_tests(&[
+            ("RADARR_BASE_URL", upstream.as_str()),
+            ("RADARR_API_KEY", "secret-key"),
+            ("RADARR_APIKEY_IN_QUERY", "true"),
+        ]);
+        let client = RadarrClient::new(
+            config.radarr.as_ref().unwrap(),
+            dir.path().join("radarr_titles.json"),
+            10,
+            &config.outbound,
+        )
+        .unwrap();
+
+        let error = client.resolve_name(603).await.unwrap_err();
+        assert!(matches!(error, RadarrError::Http(_)));
+        let chain = format!("{:?}", anyhow::Error::from(error));
+        assert!(!chain.contains("secret-key"), "{chain}");
+    }
+
+    #[test]
+    fn corrupt_cache_is_quarantined() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("radarr_titles.json");
+        std::fs::write(&path, "{\"version\": 2, \"entries\": ").unwrap();
+
+        assert!(load_cache(&path).unwrap().is_empty());
+        assert!(!path.exists());
+        assert!(dir.path().join("radarr_titles.json.corrupt").exists());
+    }
+
+    #[test]
+    fn legacy_cache_is_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("radarr_titles.json");
+        let legacy = r#"{
+            "129": {"title": "Spirited Away", "year": 2001},
+            "130": {"title": "Unknown", "year": 0}
+        }"#;
+        std::fs::write(&path, legacy).unwrap();
+
+        let entries = load_cache(&path).unwrap();
+        assert_eq!(entries[&129].title, "Spirited Away");
+        assert_eq!(entries[&129].year, Some(2001));
+        assert_eq!(entries[&130].year, None);
+    }
+}
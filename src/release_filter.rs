@@ -0,0 +1,145 @@
+use crate::releases::Torrent;
+
+/// What to do with season packs that also carry specials (see [`Torrent::mixes_specials`]).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpecialsInPacks {
+    /// Keep them; items get a `specials` attr so they can be told apart.
+    #[default]
+    Include,
+    /// Drop them from tv results.
+    Exclude,
+}
+
+impl SpecialsInPacks {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "include" => Some(Self::Include),
+            "exclude" => Some(Self::Exclude),
+            _ => None,
+        }
+    }
+}
+
+/// Release-level filters configured by the operator, compiled once at startup.
+#[derive(Debug, Clone, Default)]
+pub struct ReleaseFilter {
+    /// Lowercased `SEADEXER_BLOCKLIST` terms.
+    blocklist: Vec<String>,
+    /// Lowercased `SEADEXER_GROUP_ALLOWLIST` groups; empty allows every group.
+    group_allowlist: Vec<String>,
+}
+
+impl ReleaseFilter {
+    pub fn new(blocklist: &[String], group_allowlist: &[String]) -> Self {
+        Self {
+            blocklist: normalize(blocklist),
+            group_allowlist: normalize(group_allowlist),
+        }
+    }
+
+    /// Whether `torrent` survives the filters. The blocklist wins over the allowlist.
+    pub fn allows(&self, torrent: &Torrent) -> bool {
+        !self.blocked(torrent) && self.group_allowed(torrent)
+    }
+
+    /// With an allowlist configured, only releases whose group is listed pass; releases
+    /// without a known group are dropped.
+    fn group_allowed(&self, torrent: &Torrent) -> bool {
+        if self.group_allowlist.is_empty() {
+            return true;
+        }
+
+        torrent.release_group.as_deref().is_some_and(|group| {
+            let group = group.trim().to_lowercase();
+            self.group_allowlist.contains(&group)
+        })
+    }
+
+    /// A torrent is blocked when a term names its release group or appears in any of its
+    /// file names.
+    fn blocked(&self, torrent: &Torrent) -> bool {
+        if self.blocklist.is_empty() {
+            return false;
+        }
+
+        let group = torrent.release_group.as_deref().map(str::to_lowercase);
+        let files: Vec<String> = torrent
+            .files
+            .iter()
+            .map(|file| file.name.to_lowercase())
+            .collect();
+
+        self.blocklist.iter().any(|term| {
+            group.as_deref() == Some(term.as_str()) || files.iter().any(|name| name.contains(term))
+        })
+    }
+}
+
+fn normalize(terms: &[String]) -> Vec<String> {
+    terms
+        .iter()
+        .map(|term| term.trim().to_lowercase())
+        .filter(|term| !term.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::releases::TorrentFile;
+
+    use super::*;
+
+    fn torrent(group: Option<&str>, file: &str) -> Torrent {
+        Torrent {
+            id: "1".to_owned(),
+            download_url: String::new(),
+            source_url: String::new(),
+            info_hash: None,
+            published: None,
+            files: vec![TorrentFile {
+                length: 1,
+                name: file.to_owned(),
+            }],
+            size_bytes: 1,
+            is_best: true,
+            dual_audio: false,
+            release_group: group.map(str::to_owned),
+            anilist_id: None,
+            peers: None,
+            incomplete: false,
+        }
+    }
+
+    fn filter(blocklist: &[&str], allowlist: &[&str]) -> ReleaseFilter {
+        let owned = |terms: &[&str]| {
+            terms
+                .iter()
+                .map(|term| term.to_string())
+                .collect::<Vec<_>>()
+        };
+        ReleaseFilter::new(&owned(blocklist), &owned(allowlist))
+    }
+
+    #[test]
+    fn empty_allowlist_allows_every_group() {
+        let filter = filter(&[], &[]);
+        assert!(filter.allows(&torrent(Some("Vodes"), "Show - 01.mkv")));
+        assert!(filter.allows(&torrent(None, "Show - 01.mkv")));
+    }
+
+    #[test]
+    fn allowlist_only_passes_listed_groups() {
+        let filter = filter(&[], &[" vodes "]);
+        assert!(filter.allows(&torrent(Some("Vodes"), "Show - 01.mkv")));
+        assert!(!filter.allows(&torrent(Some("Other"), "Show - 01.mkv")));
+        assert!(!filter.allows(&torrent(None, "Show - 01.mkv")));
+    }
+
+    #[test]
+    fn blocklist_wins_over_allowlist() {
+        let filter = filter(&["vodes", "dub"], &["Vodes", "Other"]);
+        assert!(!filter.allows(&torrent(Some("Vodes"), "Show - 01.mkv")));
+        assert!(!filter.allows(&torrent(Some("Other"), "[Other] Show - 01 (DUB).mkv")));
+        assert!(filter.allows(&torrent(Some("Other"), "[Other] Show - 01.mkv")));
+    }
+}
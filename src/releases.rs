@@ -1,30 +1,57 @@
 use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 
-use reqwest::{Client, Url};
-use serde::Deserialize;
+use anyhow::Context;
+use reqwest::{
+    Client, StatusCode, Url,
+    header::{AUTHORIZATION, HeaderMap, HeaderValue, RETRY_AFTER},
+};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use time::{
+    OffsetDateTime, PrimitiveDateTime,
+    format_description::{self, well_known::Rfc3339},
+};
 use tracing::debug;
 
+use crate::config::AppConfig;
+use crate::nyaa::PeerCounts;
+
 #[derive(Debug, Clone)]
 pub struct ReleasesClient {
     http: Client,
     base_url: Url,
-    default_limit: usize,
+    max_limit: usize,
+    trackers: Vec<String>,
+    /// Name of the entries→torrents relation, used both for `expand` and in filters.
+    expand_key: String,
+    nyaa_download_template: String,
 }
 
 impl ReleasesClient {
-    pub fn new(base_url: Url, timeout: Duration, default_limit: usize) -> anyhow::Result<Self> {
-        let http = Client::builder()
-            .timeout(timeout)
-            .user_agent(format!("seadexerr/{}", env!("CARGO_PKG_VERSION")))
-            .build()?;
+    pub fn new(config: &AppConfig) -> anyhow::Result<Self> {
+        let mut builder = config.outbound.client_builder(config.releases_timeout)?;
+        if let Some(connect_timeout) = config.releases_connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        // PocketBase accepts the auth token as-is in the Authorization header.
+        if let Some(token) = config.releases_token.as_deref() {
+            let mut value = HeaderValue::from_str(token)
+                .context("SEADEXER_RELEASES_TOKEN is not a valid header value")?;
+            value.set_sensitive(true);
+            let mut headers = HeaderMap::new();
+            headers.insert(AUTHORIZATION, value);
+            builder = builder.default_headers(headers);
+        }
+        let http = builder.build()?;
 
         Ok(Self {
             http,
-            base_url,
-            default_limit,
+            base_url: config.releases_base_url.clone(),
+            max_limit: config.max_limit,
+            trackers: config.trackers.clone(),
+            expand_key: config.releases_expand_key.clone(),
+            nyaa_download_template: config.nyaa_download_template.clone(),
         })
     }
 
@@ -37,7 +64,7 @@ impl ReleasesClient {
             .fetch_entries_with(limit, |params| {
                 params.push((
                     "filter".to_string(),
-                    format!("(alID={anilist_id})&&incomplete=false"),
+                    format!("(alID={anilist_id})&&{}", self.tracker_filter()),
                 ));
             })
             .await?;
@@ -49,7 +76,8 @@ impl ReleasesClient {
             "releases.moe entries response received"
         );
 
-        let torrents: Vec<Torrent> = Self::entries_to_torrents(payload.items)
+        let torrents: Vec<Torrent> = self
+            .entries_to_torrents(payload.items)
             .into_iter()
             .take(limit)
             .collect();
@@ -70,11 +98,11 @@ impl ReleasesClient {
         let payload = self
             .fetch_entries_with(limit, |params| {
                 params.push(("sort".to_string(), "-updated".to_string()));
-                params.push(("filter".to_string(), "(incomplete=false)".to_string()));
+                params.push(("filter".to_string(), self.tracker_filter()));
             })
             .await?;
 
-        let torrents = Self::entries_to_torrents(payload.items);
+        let torrents = self.entries_to_torrents(payload.items);
 
         debug!(
             feed = "recent-public",
@@ -95,12 +123,9 @@ impl ReleasesClient {
         F: FnOnce(&mut Vec<(String, String)>),
     {
         let mut params = vec![
-            ("expand".to_string(), "trs".to_string()),
+            ("expand".to_string(), self.expand_key.clone()),
             ("page".to_string(), "1".to_string()),
-            (
-                "perPage".to_string(),
-                limit.min(self.default_limit).to_string(),
-            ),
+            ("perPage".to_string(), limit.min(self.max_limit).to_string()),
         ];
         configure(&mut params);
 
@@ -116,26 +141,107 @@ impl ReleasesClient {
             }
         }
 
-        let response = self.http.get(url).send().await?.error_for_status()?;
+        let response = Self::check_status(self.http.get(url).send().await?).await?;
         let payload: EntriesResponse = response.json().await?;
 
         Ok(payload)
     }
 
-    fn entries_to_torrents(entries: Vec<EntryRecord>) -> Vec<Torrent> {
-        entries
+    /// Turns error statuses into errors, keeping a truncated copy of the body: PocketBase
+    /// explains rejected filters there, which a bare status code doesn't.
+    async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, ReleasesError> {
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(ReleasesError::RateLimited {
+                retry_after: retry_after(&response),
+            });
+        }
+
+        if status.is_client_error() || status.is_server_error() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(ReleasesError::Status {
+                status,
+                body: truncate_body(&body),
+            });
+        }
+
+        Ok(response)
+    }
+
+    /// PocketBase filter matching entries with at least one torrent on a configured
+    /// tracker, e.g. `(trs.tracker?='Nyaa'||trs.tracker?='AB')`.
+    fn tracker_filter(&self) -> String {
+        let key = &self.expand_key;
+        let clauses = self
+            .trackers
+            .iter()
+            .map(|tracker| format!("{key}.tracker?='{}'", escape_pb_value(tracker)))
+            .collect::<Vec<_>>()
+            .join("||");
+        format!("({clauses})")
+    }
+
+    fn tracker_allowed(&self, tracker: &str) -> bool {
+        self.trackers
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(tracker))
+    }
+
+    /// Takes the expanded torrent records off an entry, or `None` when the response didn't
+    /// carry the configured expand key at all.
+    fn take_records(&self, entry: &mut EntryRecord) -> Option<Vec<TorrentRecord>> {
+        entry.expand.as_mut()?.remove(&self.expand_key)
+    }
+
+    fn entries_to_torrents(&self, entries: Vec<EntryRecord>) -> Vec<Torrent> {
+        let entry_count = entries.len();
+        let mut missing_expand = 0usize;
+        let mut missing_key = 0usize;
+        let mut empty_records = 0usize;
+        let mut records = Vec::new();
+
+        for mut entry in entries {
+            let has_expand = entry.expand.is_some();
+            match self.take_records(&mut entry) {
+                Some(trs) if trs.is_empty() => empty_records += 1,
+                Some(trs) => {
+                    let (al_id, incomplete) = (entry.al_id, entry.incomplete);
+                    records.extend(trs.into_iter().map(|record| (al_id, incomplete, record)));
+                }
+                None if has_expand => missing_key += 1,
+                None => missing_expand += 1,
+            }
+        }
+
+        let record_count = records.len();
+        let torrents: Vec<Torrent> = records
             .into_iter()
-            .flat_map(|entry| {
-                let al_id = entry.al_id;
-                entry.expand.into_iter().flat_map(move |expand| {
-                    expand.trs.into_iter().map(move |record| (al_id, record))
-                })
+            .filter(|(_, _, record)| self.tracker_allowed(&record.tracker))
+            .filter(|(_, _, record)| record.files.as_ref().is_some_and(|files| !files.is_empty()))
+            .filter(|(_, _, record)| {
+                rewritten_download_url(record, &self.nyaa_download_template).is_some()
+            })
+            .map(|(al_id, entry_incomplete, record)| {
+                let mut torrent = Torrent::from_record(record, al_id, &self.nyaa_download_template);
+                torrent.incomplete |= entry_incomplete;
+                torrent
             })
-            .filter(|(_, record)| record.tracker == "Nyaa")
-            .filter(|(_, record)| !record.tags.contains(&"Incomplete".to_string()))
-            .filter(|(_, record)| rewritten_download_url(record).is_some())
-            .map(|(al_id, record)| Torrent::from_record(record, al_id))
-            .collect()
+            .collect();
+
+        if missing_expand + missing_key + empty_records > 0 || torrents.len() < record_count {
+            debug!(
+                entries = entry_count,
+                missing_expand,
+                missing_key,
+                expand_key = %self.expand_key,
+                empty_records,
+                records = record_count,
+                filtered_records = record_count - torrents.len(),
+                "some releases.moe entries yielded no torrents"
+            );
+        }
+
+        torrents
     }
 
     pub async fn resolve_anilist_ids_for_torrents(
@@ -160,7 +266,7 @@ impl ReleasesClient {
         for chunk in unique_ids.chunks(CHUNK_SIZE.max(1)) {
             let filter = chunk
                 .iter()
-                .map(|id| format!("(trs~'{}')", id))
+                .map(|id| format!("({}~'{}')", self.expand_key, escape_pb_value(id)))
                 .collect::<Vec<_>>()
                 .join(" || ");
 
@@ -176,28 +282,31 @@ impl ReleasesClient {
             {
                 let mut pairs = url.query_pairs_mut();
                 pairs.append_pair("filter", &filter);
-                pairs.append_pair("expand", "trs");
-                let per_page = std::cmp::max(self.default_limit, chunk.len());
+                pairs.append_pair("expand", &self.expand_key);
+                let per_page = std::cmp::max(self.max_limit, chunk.len());
                 pairs.append_pair("perPage", &per_page.to_string());
             }
 
-            let response = self.http.get(url).send().await?.error_for_status()?;
+            let response = Self::check_status(self.http.get(url).send().await?).await?;
             let payload: EntriesResponse = response.json().await?;
 
             let requested: HashSet<&str> = chunk.iter().map(|id| id.as_str()).collect();
 
-            for entry in payload.items {
-                let Some(expand) = entry.expand else { continue };
+            for mut entry in payload.items {
                 let Some(al_id) = entry.al_id else { continue };
+                let Some(records) = self.take_records(&mut entry) else {
+                    continue;
+                };
 
-                for record in expand.trs {
-                    if record.tracker != "Nyaa" {
+                for record in records {
+                    if !self.tracker_allowed(&record.tracker) {
                         continue;
                     }
 
-                    if requested.contains(record.id.as_str()) {
-                        result.insert(record.id, al_id);
+                    if !requested.contains(record.id.as_str()) {
+                        continue;
                     }
+                    record_anilist_id(&mut result, &record.id, al_id);
                 }
             }
         }
@@ -206,6 +315,39 @@ impl ReleasesClient {
     }
 }
 
+/// Gives torrents without an alID the one another entry in the same batch lists them
+/// under. An entry-level alID is authoritative, so these copies skip the reverse lookup.
+pub fn share_entry_anilist_ids(torrents: &mut [Torrent]) {
+    let mut entry_ids = HashMap::new();
+    for torrent in torrents.iter() {
+        if let Some(anilist_id) = torrent.anilist_id {
+            record_anilist_id(&mut entry_ids, &torrent.id, anilist_id);
+        }
+    }
+    for torrent in torrents.iter_mut() {
+        if torrent.anilist_id.is_none() {
+            torrent.anilist_id = entry_ids.get(&torrent.id).copied();
+        }
+    }
+}
+
+/// Several entries can list the same torrent; keeps the first AniList id seen rather than
+/// letting whichever comes last decide the media, and logs the disagreement.
+fn record_anilist_id(ids: &mut HashMap<String, i64>, torrent_id: &str, anilist_id: i64) {
+    match ids.get(torrent_id) {
+        Some(&kept) if kept != anilist_id => debug!(
+            torrent = torrent_id,
+            kept,
+            ignored = anilist_id,
+            "torrent listed under several AniList entries"
+        ),
+        Some(_) => {}
+        None => {
+            ids.insert(torrent_id.to_owned(), anilist_id);
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct EntriesResponse {
     items: Vec<EntryRecord>,
@@ -215,13 +357,12 @@ struct EntriesResponse {
 struct EntryRecord {
     #[serde(rename = "alID")]
     al_id: Option<i64>,
-    expand: Option<EntryExpand>,
-}
-
-#[derive(Debug, Clone, Deserialize)]
-struct EntryExpand {
+    /// Set while a show is still airing and its batch doesn't cover every episode yet.
     #[serde(default)]
-    trs: Vec<TorrentRecord>,
+    incomplete: bool,
+    /// Expanded relations keyed by relation name; the torrents live under the configured
+    /// expand key (`trs`).
+    expand: Option<HashMap<String, Vec<TorrentRecord>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -234,33 +375,102 @@ pub struct Torrent {
     pub files: Vec<TorrentFile>,
     pub size_bytes: u64,
     pub is_best: bool,
+    pub dual_audio: bool,
+    pub release_group: Option<String>,
     pub anilist_id: Option<i64>,
+    pub peers: Option<PeerCounts>,
+    /// Partial batch, either from the entry's `incomplete` flag or an `Incomplete` tag.
+    pub incomplete: bool,
 }
 
 impl Torrent {
-    fn from_record(record: TorrentRecord, anilist_id: Option<i64>) -> Self {
-        let download_url = rewritten_download_url(&record).unwrap_or_else(|| record.url.clone());
+    fn from_record(
+        record: TorrentRecord,
+        anilist_id: Option<i64>,
+        nyaa_download_template: &str,
+    ) -> Self {
+        let download_url = rewritten_download_url(&record, nyaa_download_template)
+            .unwrap_or_else(|| record.url.clone());
         let source_url = record.url.clone();
-
-        let size_bytes = record.files.iter().map(|f| f.length).sum::<u64>();
+        let files = record.files.unwrap_or_default();
+        let incomplete = record.tags.iter().any(|tag| tag == "Incomplete");
+
+        // Prefer the torrent-level size reported by releases.moe; the file sum can
+        // under-count when the torrent carries padding files.
+        let size_bytes = record
+            .size
+            .filter(|size| *size > 0)
+            .unwrap_or_else(|| files.iter().map(|f| f.length).sum::<u64>());
         Torrent {
             id: record.id,
             download_url,
-            info_hash: record.info_hash,
+            info_hash: record
+                .info_hash
+                .or_else(|| extract_magnet_hash(&record.url).map(str::to_owned)),
             published: record
                 .updated
                 .as_deref()
                 .and_then(parse_timestamp)
                 .or_else(|| record.created.as_deref().and_then(parse_timestamp)),
-            files: record.files,
+            files,
             size_bytes,
             is_best: record.is_best,
+            dual_audio: record.dual_audio,
+            release_group: record
+                .release_group
+                .filter(|group| !group.trim().is_empty()),
             anilist_id,
             source_url,
+            peers: None,
+            incomplete,
+        }
+    }
+
+    /// Whether a batch bundles specials (OVAs, OADs, `S00` episodes) with regular
+    /// episodes. Sonarr imports such a pack as the season in its title and mismatches the
+    /// extra files. Only video files count, so subtitles and extras folders don't trip it.
+    pub fn mixes_specials(&self) -> bool {
+        let mut specials = false;
+        let mut episodes = false;
+        for file in self.files.iter().filter(|file| is_video_file(&file.name)) {
+            if is_special_file(&file.name) {
+                specials = true;
+            } else {
+                episodes = true;
+            }
+            if specials && episodes {
+                return true;
+            }
         }
+        false
     }
 }
 
+fn is_video_file(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    [".mkv", ".mp4", ".avi", ".m2ts"]
+        .iter()
+        .any(|extension| name.ends_with(extension))
+}
+
+/// Matches special-episode markers as whole words anywhere in the path, e.g.
+/// `Show - OVA.mkv`, `Specials/Show - SP01.mkv` or `Show S00E01.mkv`.
+fn is_special_file(name: &str) -> bool {
+    name.to_ascii_lowercase()
+        .split(|ch: char| !ch.is_ascii_alphanumeric())
+        .any(|word| {
+            matches!(
+                word,
+                "ova" | "ovas" | "oad" | "oads" | "special" | "specials"
+            ) || word
+                .strip_prefix("s00e")
+                .is_some_and(|rest| !rest.is_empty())
+                || word.strip_prefix("sp").is_some_and(|rest| {
+                    !rest.is_empty() && rest.bytes().all(|byte| byte.is_ascii_digit())
+                })
+        })
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct TorrentRecord {
     id: String,
@@ -272,10 +482,19 @@ struct TorrentRecord {
     updated: Option<String>,
     #[serde(rename = "isBest")]
     is_best: bool,
+    #[serde(rename = "dualAudio", default)]
+    dual_audio: bool,
     tags: Vec<String>,
     #[serde(default)]
     tracker: String,
-    files: Vec<TorrentFile>,
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(rename = "releaseGroup", default)]
+    release_group: Option<String>,
+    /// Occasionally `null` or missing; such records are skipped rather than failing the
+    /// whole response.
+    #[serde(default)]
+    files: Option<Vec<TorrentFile>>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -285,34 +504,213 @@ pub struct TorrentFile {
     pub name: String,
 }
 
+/// Points [`score_torrent`] adds up for each quality signal of a release.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreWeights {
+    pub base: u32,
+    pub best: u32,
+    pub dual_audio: u32,
+    /// Added once per resolution tier above SD: 720p, 1080p and 2160p.
+    pub resolution: u32,
+    pub trusted_group: u32,
+}
+
+impl Default for ScoreWeights {
+    /// Keeps a plain best pick at the 1000 seeders it reported before scoring existed.
+    fn default() -> Self {
+        Self {
+            base: 100,
+            best: 900,
+            dual_audio: 50,
+            resolution: 10,
+            trusted_group: 100,
+        }
+    }
+}
+
+impl ScoreWeights {
+    /// Applies comma-separated `key=value` overrides such as `best=500,dual_audio=0` on top
+    /// of the defaults.
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let mut weights = Self::default();
+        for entry in value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (key, weight) = entry
+                .split_once('=')
+                .with_context(|| format!("score weight `{entry}` must look like `key=value`"))?;
+            let weight: u32 = weight
+                .trim()
+                .parse()
+                .with_context(|| format!("score weight `{entry}` must be a whole number"))?;
+            let slot = match key.trim() {
+                "base" => &mut weights.base,
+                "best" => &mut weights.best,
+                "dual_audio" => &mut weights.dual_audio,
+                "resolution" => &mut weights.resolution,
+                "trusted_group" => &mut weights.trusted_group,
+                other => anyhow::bail!("unknown score weight `{other}`"),
+            };
+            *slot = weight;
+        }
+        Ok(weights)
+    }
+}
+
+/// Ranks a release by SeaDex's quality signals. Reported as the seeder count when no live
+/// peer counts are available, so clients order interactive results by it.
+pub fn score_torrent(torrent: &Torrent, config: &AppConfig) -> u32 {
+    let weights = &config.score_weights;
+    let mut score = weights.base;
+    if torrent.is_best {
+        score = score.saturating_add(weights.best);
+    }
+    if torrent.dual_audio {
+        score = score.saturating_add(weights.dual_audio);
+    }
+    score = score.saturating_add(weights.resolution.saturating_mul(resolution_tier(torrent)));
+    if let Some(group) = &torrent.release_group
+        && config
+            .trusted_groups
+            .iter()
+            .any(|trusted| trusted.eq_ignore_ascii_case(group.trim()))
+    {
+        score = score.saturating_add(weights.trusted_group);
+    }
+    score
+}
+
+/// Highest resolution named in any file of the release: 0 for SD or unknown, up to 3 for
+/// 2160p.
+fn resolution_tier(torrent: &Torrent) -> u32 {
+    torrent
+        .files
+        .iter()
+        .map(|file| {
+            let name = file.name.to_ascii_lowercase();
+            if name.contains("2160p") || name.contains("4k") {
+                3
+            } else if name.contains("1080p") {
+                2
+            } else if name.contains("720p") {
+                1
+            } else {
+                0
+            }
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Escapes a value for use inside a single-quoted PocketBase filter string literal.
+fn escape_pb_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+/// Reads a `Retry-After` header expressed in seconds.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+/// Formats tried after RFC 3339, once a space separator has been replaced with `T`.
+/// PocketBase may emit `+0000` style offsets that RFC 3339 rejects.
+const OFFSET_TIMESTAMP_FORMATS: &[&str] = &[
+    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond][offset_hour sign:mandatory][offset_minute]",
+    "[year]-[month]-[day]T[hour]:[minute]:[second][offset_hour sign:mandatory][offset_minute]",
+];
+
+/// Offset-less formats, interpreted as UTC.
+const UTC_TIMESTAMP_FORMATS: &[&str] = &[
+    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond]",
+    "[year]-[month]-[day]T[hour]:[minute]:[second]",
+];
+
 fn parse_timestamp(value: &str) -> Option<OffsetDateTime> {
-    if let Ok(parsed) = OffsetDateTime::parse(value, &Rfc3339) {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    // PocketBase renders `2024-01-15 10:30:45.123Z`
+    let normalized = value.replacen(' ', "T", 1);
+    if let Ok(parsed) = OffsetDateTime::parse(&normalized, &Rfc3339) {
         return Some(parsed);
     }
 
-    let mut normalized = value.replace(' ', "T");
-    if !normalized.ends_with('Z') {
-        normalized.push('Z');
+    for format in OFFSET_TIMESTAMP_FORMATS {
+        if let Ok(description) = format_description::parse_borrowed::<2>(format)
+            && let Ok(parsed) = OffsetDateTime::parse(&normalized, &description)
+        {
+            return Some(parsed);
+        }
+    }
+
+    for format in UTC_TIMESTAMP_FORMATS {
+        if let Ok(description) = format_description::parse_borrowed::<2>(format)
+            && let Ok(parsed) = PrimitiveDateTime::parse(&normalized, &description)
+        {
+            return Some(parsed.assume_utc());
+        }
     }
 
-    OffsetDateTime::parse(&normalized, &Rfc3339).ok()
+    debug!(value, "unrecognised releases.moe timestamp");
+    None
 }
 
-fn rewritten_download_url(record: &TorrentRecord) -> Option<String> {
-    extract_nyaa_id(record.url.as_str()).map(|id| format!("https://nyaa.si/download/{id}.torrent"))
+/// Default for `SEADEXER_NYAA_DOWNLOAD_BASE`.
+pub const DEFAULT_NYAA_DOWNLOAD_TEMPLATE: &str = "https://nyaa.si/download/{id}.torrent";
+
+fn rewritten_download_url(record: &TorrentRecord, nyaa_download_template: &str) -> Option<String> {
+    if let Some(id) = extract_nyaa_id(record.url.as_str()) {
+        return Some(nyaa_download_template.replace("{id}", id));
+    }
+
+    let info_hash = record
+        .info_hash
+        .as_deref()
+        .filter(|hash| !hash.is_empty())
+        .or_else(|| extract_magnet_hash(record.url.as_str()))?;
+    Some(format!("magnet:?xt=urn:btih:{info_hash}"))
 }
 
-fn extract_nyaa_id(url: &str) -> Option<&str> {
-    let needle = "/view/";
-    let start = url.find(needle)? + needle.len();
-    let rest = &url[start..];
-    let id = rest.split(['?', '#', '/']).next().unwrap_or("");
+/// Extracts the numeric nyaa id from `/view/<id>` and `?page=view&tid=<id>` style URLs.
+pub(crate) fn extract_nyaa_id(url: &str) -> Option<&str> {
+    let id = if let Some(start) = url.find("/view/") {
+        url[start + "/view/".len()..]
+            .split(['?', '#', '/'])
+            .next()
+            .unwrap_or("")
+    } else {
+        let query = url.split_once('?')?.1;
+        query
+            .split(['&', '#'])
+            .find_map(|pair| pair.strip_prefix("tid="))?
+    };
+
     if id.is_empty() || !id.chars().all(|ch| ch.is_ascii_digit()) {
         return None;
     }
     Some(id)
 }
 
+fn extract_magnet_hash(url: &str) -> Option<&str> {
+    let query = url.strip_prefix("magnet:?")?;
+    let hash = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("xt=urn:btih:"))?;
+    if hash.is_empty() || !hash.chars().all(|ch| ch.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some(hash)
+}
+
 #[derive(Debug, Error)]
 pub enum ReleasesError {
     #[error("failed to build releases.moe request url")]
@@ -321,4 +719,198 @@ pub enum ReleasesError {
     Http(#[from] reqwest::Error),
     #[error("failed to deserialise releases.moe response payload")]
     Deserialisation(#[from] serde_json::Error),
+    #[error("releases.moe rate limit exceeded")]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("releases.moe returned {status}: {body}")]
+    Status { status: StatusCode, body: String },
+}
+
+/// Longest error body kept on [`ReleasesError::Status`].
+const ERROR_BODY_LIMIT: usize = 512;
+
+/// Collapses the body onto one line and cuts it to [`ERROR_BODY_LIMIT`] characters.
+fn truncate_body(body: &str) -> String {
+    let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    match body.char_indices().nth(ERROR_BODY_LIMIT) {
+        Some((end, _)) => format!("{}…", &body[..end]),
+        None => body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn record(id: &str) -> serde_json::Value {
+        json!({
+            "id": id,
+            "url": format!("https://nyaa.si/view/{id}"),
+            "isBest": true,
+            "tags": [],
+            "tracker": "Nyaa",
+            "files": [{ "name": "Show - 01.mkv", "length": 1 }],
+        })
+    }
+
+    fn torrent(id: &str, anilist_id: Option<i64>) -> Torrent {
+        let record = serde_json::from_value(record(id)).unwrap();
+        Torrent::from_record(record, anilist_id, DEFAULT_NYAA_DOWNLOAD_TEMPLATE)
+    }
+
+    const HEX_HASH: &str = "c12fe1c06bba254a9dc9f519b335aa7c1367a88a";
+    const BASE32_HASH: &str = "YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK";
+
+    #[test]
+    fn nyaa_ids_come_from_view_and_tid_urls() {
+        let magnet = format!("magnet:?xt=urn:btih:{HEX_HASH}");
+        for (url, expected) in [
+            ("https://nyaa.si/view/1234567", Some("1234567")),
+            ("https://nyaa.si/view/1234567/", Some("1234567")),
+            ("https://nyaa.si/view/1234567#comments", Some("1234567")),
+            ("https://nyaa.si/?page=view&tid=1234567", Some("1234567")),
+            ("https://nyaa.si/?tid=1234567&page=view", Some("1234567")),
+            ("https://nyaa.si/?page=view&tid=abc", None),
+            ("https://nyaa.si/view/", None),
+            ("https://example.com/files/show.torrent", None),
+            (magnet.as_str(), None),
+        ] {
+            assert_eq!(extract_nyaa_id(url), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn magnet_hashes_accept_hex_and_base32() {
+        for (url, expected) in [
+            (
+                format!("magnet:?xt=urn:btih:{HEX_HASH}&dn=Show"),
+                Some(HEX_HASH),
+            ),
+            (
+                format!("magnet:?dn=Show&xt=urn:btih:{BASE32_HASH}"),
+                Some(BASE32_HASH),
+            ),
+            ("magnet:?xt=urn:btih:&dn=Show".to_owned(), None),
+            ("magnet:?dn=Show".to_owned(), None),
+            ("https://nyaa.si/view/1234567".to_owned(), None),
+        ] {
+            assert_eq!(extract_magnet_hash(&url), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn download_urls_follow_the_record_url_shape() {
+        for (url, expected) in [
+            (
+                "https://nyaa.si/?page=view&tid=1234567".to_owned(),
+                "https://nyaa.si/download/1234567.torrent".to_owned(),
+            ),
+            (
+                format!("magnet:?xt=urn:btih:{BASE32_HASH}&dn=Show"),
+                format!("magnet:?xt=urn:btih:{BASE32_HASH}"),
+            ),
+        ] {
+            let mut value = record("1");
+            value["url"] = json!(url);
+            let record: TorrentRecord = serde_json::from_value(value).unwrap();
+            let torrent = Torrent::from_record(record, None, DEFAULT_NYAA_DOWNLOAD_TEMPLATE);
+            assert_eq!(torrent.download_url, expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn records_without_files_are_skipped() {
+        let mut missing = record("2");
+        missing.as_object_mut().unwrap().remove("files");
+        let mut nulled = record("3");
+        nulled["files"] = serde_json::Value::Null;
+
+        let payload: EntriesResponse = serde_json::from_value(json!({
+            "items": [{ "alID": 21, "expand": { "trs": [record("1"), missing, nulled] } }],
+        }))
+        .unwrap();
+        let client = ReleasesClient::new(&AppConfig::for_tests(&[])).unwrap();
+        let torrents = client.entries_to_torrents(payload.items);
+
+        let ids: Vec<_> = torrents.iter().map(|torrent| torrent.id.as_str()).collect();
+        assert_eq!(ids, ["1"]);
+    }
+
+    #[test]
+    fn incomplete_entries_and_tags_mark_torrents() {
+        let mut tagged = record("2");
+        tagged["tags"] = json!(["Incomplete"]);
+
+        let payload: EntriesResponse = serde_json::from_value(json!({
+            "items": [
+                { "alID": 21, "incomplete": true, "expand": { "trs": [record("1")] } },
+                { "alID": 22, "expand": { "trs": [tagged, record("3")] } },
+            ],
+        }))
+        .unwrap();
+        let client = ReleasesClient::new(&AppConfig::for_tests(&[])).unwrap();
+        let torrents = client.entries_to_torrents(payload.items);
+
+        let flags: Vec<_> = torrents
+            .iter()
+            .map(|torrent| (torrent.id.as_str(), torrent.incomplete))
+            .collect();
+        assert_eq!(flags, [("1", true), ("2", true), ("3", false)]);
+    }
+
+    #[test]
+    fn conflicting_entries_keep_the_first_anilist_id() {
+        let mut ids = HashMap::new();
+        record_anilist_id(&mut ids, "t1", 21);
+        record_anilist_id(&mut ids, "t1", 22);
+        assert_eq!(ids.get("t1"), Some(&21));
+
+        let mut torrents = vec![
+            torrent("t1", Some(21)),
+            torrent("t1", Some(22)),
+            torrent("t1", None),
+            torrent("t2", None),
+        ];
+        share_entry_anilist_ids(&mut torrents);
+
+        let anilist_ids: Vec<_> = torrents.iter().map(|torrent| torrent.anilist_id).collect();
+        assert_eq!(anilist_ids, [Some(21), Some(22), Some(21), None]);
+    }
+
+    #[test]
+    fn parses_pocketbase_timestamps() {
+        let utc = 1_705_314_645;
+        for (value, expected) in [
+            ("2024-01-15 10:30:45.123Z", utc),
+            ("2024-01-15 10:30:45Z", utc),
+            ("2024-01-15T10:30:45.000Z", utc),
+            ("2024-01-15 10:30:45.123", utc),
+            ("2024-01-15 10:30:45", utc),
+            ("2024-01-15 11:30:45.123+0100", utc),
+            ("2024-01-15T11:30:45+01:00", utc),
+            (" 2024-01-15 09:30:45-0100 ", utc),
+        ] {
+            let parsed = parse_timestamp(value).unwrap_or_else(|| panic!("{value}"));
+            assert_eq!(parsed.unix_timestamp(), expected, "{value}");
+        }
+
+        assert!(parse_timestamp("").is_none());
+        assert!(parse_timestamp("yesterday").is_none());
+    }
+
+    #[test]
+    fn filter_values_escape_quotes_and_backslashes() {
+        assert_eq!(escape_pb_value("plain"), "plain");
+        assert_eq!(escape_pb_value("it's"), r"it\'s");
+        assert_eq!(escape_pb_value(r"a\b"), r"a\\b");
+        assert_eq!(escape_pb_value(r"\'"), r"\\\'");
+
+        let config = AppConfig::for_tests(&[("SEADEXER_TRACKERS", "Nyaa,O'Neil")]);
+        let client = ReleasesClient::new(&config).unwrap();
+        assert_eq!(
+            client.tracker_filter(),
+            r"(trs.tracker?='Nyaa'||trs.tracker?='O\'Neil')"
+        );
+    }
 }
@@ -1,48 +1,73 @@
 use std::{
     collections::{HashMap, HashSet},
-    io::ErrorKind,
+    io::{ErrorKind, Write},
     path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder, Response};
 use serde::Deserialize;
 use thiserror::Error;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 use tokio::task;
-use tracing::debug;
+use tracing::{debug, warn};
 use url::Url;
 
+use crate::config::{OutboundConfig, SonarrConfig};
+use crate::title_cache::{CachedTitle, TitleCache, decode_cache, encode_cache};
+
 #[derive(Debug, Clone)]
 pub struct SonarrClient {
     http: Client,
     base_url: Url,
     api_key: String,
-    cache: Arc<RwLock<HashMap<i64, String>>>,
+    api_key_in_query: bool,
+    cache: Arc<RwLock<TitleCache<CachedTitle>>>,
     cache_path: PathBuf,
+    /// Ids seeded by the last prewarm; pruning leaves them alone.
+    prewarmed: Arc<RwLock<HashSet<i64>>>,
+    /// Serialises cache writes, which share one temp file.
+    persist_lock: Arc<Mutex<()>>,
+    misses: Arc<RwLock<HashMap<i64, Instant>>>,
+    library_only: bool,
+    library: Arc<RwLock<Option<HashSet<i64>>>>,
 }
 
+/// How long a tvdb id Sonarr couldn't resolve is answered from memory before asking again.
+const NOT_FOUND_TTL: Duration = Duration::from_secs(600);
+/// Attempts made for a lookup that fails with a connection error or a 5xx response.
+const LOOKUP_ATTEMPTS: u32 = 3;
+const LOOKUP_BACKOFF: Duration = Duration::from_millis(250);
+/// How often the monitored series are re-read for library-only mode and title prewarming.
+const LIBRARY_REFRESH_INTERVAL: Duration = Duration::from_secs(900);
+
 impl SonarrClient {
     pub fn new(
-        base_url: Url,
-        api_key: String,
-        timeout: Duration,
+        config: &SonarrConfig,
         cache_path: PathBuf,
+        cache_max: usize,
+        outbound: &OutboundConfig,
     ) -> anyhow::Result<Self> {
-        let http = Client::builder()
-            .timeout(timeout)
-            .user_agent(format!("seadexerr/{}", env!("CARGO_PKG_VERSION")))
+        let http = outbound
+            .client_builder(config.timeout)?
+            .danger_accept_invalid_certs(config.insecure)
             .build()?;
 
-        let cache = load_cache(&cache_path)?;
+        let cache = TitleCache::from_entries(load_cache(&cache_path)?, cache_max);
 
         Ok(Self {
             http,
-            base_url,
-            api_key,
+            base_url: config.url.clone(),
+            api_key: config.api_key.clone(),
+            api_key_in_query: config.api_key_in_query,
             cache: Arc::new(RwLock::new(cache)),
             cache_path,
+            prewarmed: Arc::new(RwLock::new(HashSet::new())),
+            persist_lock: Arc::new(Mutex::new(())),
+            misses: Arc::new(RwLock::new(HashMap::new())),
+            library_only: config.library_only,
+            library: Arc::new(RwLock::new(None)),
         })
     }
 
@@ -52,6 +77,16 @@ impl SonarrClient {
             return Ok(cached);
         }
 
+        {
+            let guard = self.misses.read().await;
+            if let Some(recorded) = guard.get(&tvdb_id)
+                && recorded.elapsed() < NOT_FOUND_TTL
+            {
+                debug!(tvdb_id, "Sonarr lookup miss served from negative cache");
+                return Err(SonarrError::NotFound { tvdb_id });
+            }
+        }
+
         let mut url = self
             .base_url
             .join("api/v3/series/lookup")
@@ -68,13 +103,7 @@ impl SonarrClient {
             "requesting Sonarr series lookup"
         );
 
-        let response = self
-            .http
-            .get(url)
-            .header("X-Api-Key", &self.api_key)
-            .send()
-            .await?
-            .error_for_status()?;
+        let response = self.lookup_with_retry(url).await?;
 
         let payload: Vec<SeriesLookupEntry> = response.json().await?;
 
@@ -85,6 +114,9 @@ impl SonarrClient {
         );
 
         let Some(title) = payload.into_iter().find_map(|entry| entry.title) else {
+            let mut guard = self.misses.write().await;
+            guard.retain(|_, recorded| recorded.elapsed() < NOT_FOUND_TTL);
+            guard.insert(tvdb_id, Instant::now());
             return Err(SonarrError::NotFound { tvdb_id });
         };
 
@@ -93,66 +125,187 @@ impl SonarrClient {
         Ok(title)
     }
 
-    pub async fn retain_titles(&self, keep: &HashSet<i64>) -> Result<(), SonarrError> {
-        if keep.is_empty() {
-            let mut guard = self.cache.write().await;
-            if guard.is_empty() {
-                return Ok(());
+    pub fn library_only(&self) -> bool {
+        self.library_only
+    }
+
+    /// Whether `tvdb_id` is a monitored series in Sonarr. Everything is allowed until the
+    /// library has been loaded once, so a slow Sonarr doesn't empty the feed at startup.
+    pub async fn in_library(&self, tvdb_id: i64) -> bool {
+        let guard = self.library.read().await;
+        guard
+            .as_ref()
+            .is_none_or(|library| library.contains(&tvdb_id))
+    }
+
+    /// Re-reads the monitored series from Sonarr on a timer while library-only mode is on
+    /// and, with `prewarm`, seeds the title cache with their titles.
+    pub fn spawn_library_refresh(&self, prewarm: bool) {
+        if !self.library_only && !prewarm {
+            return;
+        }
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match this.refresh_library(prewarm).await {
+                    Ok(series) => debug!(series, prewarm, "refreshed Sonarr library"),
+                    Err(error) => warn!(error = %error, "failed to refresh Sonarr library"),
+                }
+                tokio::time::sleep(LIBRARY_REFRESH_INTERVAL).await;
             }
-            guard.clear();
-            drop(guard);
-            return self.persist_cache().await;
+        });
+    }
+
+    async fn refresh_library(&self, prewarm: bool) -> Result<usize, SonarrError> {
+        let url = self
+            .base_url
+            .join("api/v3/series")
+            .map_err(SonarrError::Url)?;
+        let response = self.lookup_with_retry(url).await?;
+        let payload: Vec<LibrarySeriesEntry> = response.json().await?;
+
+        let monitored: Vec<(i64, Option<String>)> = payload
+            .into_iter()
+            .filter(|entry| entry.monitored)
+            .filter_map(|entry| Some((entry.tvdb_id?, entry.title)))
+            .collect();
+        let series = monitored.len();
+
+        if prewarm {
+            let mut prewarmed = HashSet::new();
+            {
+                let mut guard = self.cache.write().await;
+                for (tvdb_id, title) in &monitored {
+                    if let Some(title) = title {
+                        guard.insert(*tvdb_id, CachedTitle::new(title.clone(), None));
+                        prewarmed.insert(*tvdb_id);
+                    }
+                }
+            }
+            *self.prewarmed.write().await = prewarmed;
+            self.persist_cache().await?;
+        }
+
+        if self.library_only {
+            let library: HashSet<i64> = monitored.into_iter().map(|(tvdb_id, _)| tvdb_id).collect();
+            *self.library.write().await = Some(library);
         }
+        Ok(series)
+    }
 
+    /// Prunes the title cache down to `keep`, sparing titles seeded by the prewarm so the
+    /// next feed doesn't throw away what was loaded ahead of it.
+    pub async fn retain_titles(&self, keep: &HashSet<i64>) -> Result<(), SonarrError> {
+        let prewarmed = self.prewarmed.read().await;
         let mut guard = self.cache.write().await;
         let original_len = guard.len();
-        guard.retain(|tvdb_id, _| keep.contains(tvdb_id));
+        guard.retain(|tvdb_id| keep.contains(tvdb_id) || prewarmed.contains(tvdb_id));
 
         if guard.len() == original_len {
             return Ok(());
         }
 
         drop(guard);
+        drop(prewarmed);
         self.persist_cache().await
     }
 
+    /// Sends a lookup, retrying with exponential backoff while Sonarr is briefly
+    /// unreachable or answering with server errors.
+    async fn lookup_with_retry(&self, url: Url) -> Result<Response, SonarrError> {
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .authorised_get(url.clone())
+                .send()
+                .await
+                .and_then(Response::error_for_status);
+
+            match result {
+                Ok(response) => return Ok(response),
+                Err(error) if attempt < LOOKUP_ATTEMPTS && is_transient(&error) => {
+                    let delay = LOOKUP_BACKOFF * 2u32.pow(attempt - 1);
+                    warn!(
+                        error = %error.without_url(),
+                        attempt,
+                        delay_ms = delay.as_millis() as u64,
+                        "Sonarr lookup failed; retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Builds a GET request carrying the API key, either as the `X-Api-Key` header or,
+    /// for proxies that strip custom headers, as an `apikey` query parameter.
+    fn authorised_get(&self, mut url: Url) -> RequestBuilder {
+        if self.api_key_in_query {
+            url.query_pairs_mut().append_pair("apikey", &self.api_key);
+            self.http.get(url)
+        } else {
+            self.http.get(url).header("X-Api-Key", &self.api_key)
+        }
+    }
+
     async fn cached_title(&self, tvdb_id: i64) -> Option<String> {
-        let guard = self.cache.read().await;
-        guard.get(&tvdb_id).cloned()
+        let mut guard = self.cache.write().await;
+        guard.get(tvdb_id).map(|cached| cached.title)
     }
 
     async fn store_title(&self, tvdb_id: i64, title: &str) -> Result<(), SonarrError> {
         {
             let mut guard = self.cache.write().await;
-            guard.insert(tvdb_id, title.to_string());
+            guard.insert(tvdb_id, CachedTitle::new(title.to_string(), None));
         }
         self.persist_cache().await
     }
 
     async fn persist_cache(&self) -> Result<(), SonarrError> {
+        // held until the rename, so the snapshot written last is also the newest one
+        let _persist = self.persist_lock.lock().await;
         // Clone snapshot under the read lock, then offload serialization + write
         // to a blocking thread to avoid blocking tokio worker threads.
         let snapshot = {
             let guard = self.cache.read().await;
-            guard.clone()
+            guard.entries().clone()
         };
 
         let path = self.cache_path.clone();
 
-        let result = task::spawn_blocking(move || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-            let json = serde_json::to_vec_pretty(&snapshot)?;
-
-            if let Some(parent) = path.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-
-            std::fs::write(&path, json)?;
-
-            Ok(())
-        })
+        let result = task::spawn_blocking(
+            move || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+                let json = encode_cache(&snapshot)?;
+
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+
+                // Write to a temp file and fsync it before renaming so a crash can't leave a
+                // truncated cache behind.
+                let temp_path = path.with_extension("json.tmp");
+                {
+                    let mut file = std::fs::File::create(&temp_path)?;
+                    file.write_all(&json)?;
+                    file.sync_all()?;
+                }
+                std::fs::rename(&temp_path, &path)?;
+
+                if let Some(parent) = path.parent()
+                    && let Ok(dir) = std::fs::File::open(parent)
+                {
+                    let _ = dir.sync_all();
+                }
+
+                Ok(())
+            },
+        )
         .await
         .map_err(|source| SonarrError::CacheWrite {
-            source: std::io::Error::new(std::io::ErrorKind::Other, format!("join error: {source}")),
+            source: std::io::Error::other(format!("join error: {source}")),
             path: self.cache_path.clone(),
         })?;
 
@@ -160,7 +313,7 @@ impl SonarrClient {
             // For simplicity, map any persistence error to CacheWrite. We avoid trying to
             // downcast boxed errors back to concrete types here.
             return Err(SonarrError::CacheWrite {
-                source: std::io::Error::new(std::io::ErrorKind::Other, "failed to persist cache"),
+                source: std::io::Error::other("failed to persist cache"),
                 path: self.cache_path.clone(),
             });
         }
@@ -169,13 +322,32 @@ impl SonarrClient {
     }
 }
 
+fn is_transient(error: &reqwest::Error) -> bool {
+    error.is_connect()
+        || error.is_timeout()
+        || error
+            .status()
+            .is_some_and(|status| status.is_server_error())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LibrarySeriesEntry {
+    #[serde(default)]
+    tvdb_id: Option<i64>,
+    #[serde(default)]
+    monitored: bool,
+    #[serde(default)]
+    title: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 struct SeriesLookupEntry {
     #[serde(default)]
     title: Option<String>,
 }
 
-fn load_cache(path: &Path) -> Result<HashMap<i64, String>, SonarrError> {
+fn load_cache(path: &Path) -> Result<HashMap<i64, CachedTitle>, SonarrError> {
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent).map_err(|source| SonarrError::CacheDir {
             source,
@@ -198,13 +370,24 @@ fn load_cache(path: &Path) -> Result<HashMap<i64, String>, SonarrError> {
         return Ok(HashMap::new());
     }
 
-    let data: HashMap<i64, String> =
-        serde_json::from_slice(&bytes).map_err(|source| SonarrError::CacheParse {
-            source,
-            path: path.to_path_buf(),
-        })?;
-
-    Ok(data)
+    // releases before the versioned format stored bare titles
+    match decode_cache(&bytes, |title: String| CachedTitle::new(title, None)) {
+        Ok(data) => Ok(data),
+        Err(error) => {
+            // A corrupt cache only costs a few extra lookups, so start empty rather than
+            // refusing to start.
+            let quarantine_path = path.with_extension("json.corrupt");
+            let moved = std::fs::rename(path, &quarantine_path).is_ok();
+            warn!(
+                error = %error,
+                path = %path.display(),
+                quarantine = %quarantine_path.display(),
+                moved,
+                "cached Sonarr titles were corrupt; starting with an empty cache"
+            );
+            Ok(HashMap::new())
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -212,7 +395,7 @@ pub enum SonarrError {
     #[error("failed to build Sonarr request url")]
     Url(#[from] url::ParseError),
     #[error("http error when querying Sonarr api")]
-    Http(#[from] reqwest::Error),
+    Http(#[source] reqwest::Error),
     #[error("no Sonarr series title found for tvdb {tvdb_id}")]
     NotFound { tvdb_id: i64 },
     #[error("failed to read cached Sonarr titles at {path}")]
@@ -227,12 +410,6 @@ pub enum SonarrError {
         source: std::io::Error,
         path: PathBuf,
     },
-    #[error("failed to parse cached Sonarr titles at {path}")]
-    CacheParse {
-        #[source]
-        source: serde_json::Error,
-        path: PathBuf,
-    },
     #[error("failed to serialise cached Sonarr titles")]
     CacheSerialise(#[from] serde_json::Error),
     #[error("failed to create cache directory at {path}")]
@@ -242,3 +419,69 @@ pub enum SonarrError {
         path: PathBuf,
     },
 }
+
+impl From<reqwest::Error> for SonarrError {
+    /// Drops the request URL, which carries the API key with `SONARR_APIKEY_IN_QUERY`.
+    fn from(error: reqwest::Error) -> Self {
+        Self::Http(error.without_url())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn http_errors_leave_out_the_query_api_key() {
+        let upstream =
+            test_support::serve(Router::new().fallback(|| async { StatusCode::FORBIDDEN })).await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = AppConfig::for_tests(&[
+            ("SONARR_BASE_URL", upstream.as_str()),
+            ("SONARR_API_KEY", "secret-key"),
+            ("SONARR_APIKEY_IN_QUERY", "true"),
+        ]);
+        let client = SonarrClient::new(
+            config.sonarr.as_ref().unwrap(),
+            dir.path().join("sonarr_titles.json"),
+            10,
+            &config.outbound,
+        )
+        .unwrap();
+
+        let error = client.resolve_name(81797).await.unwrap_err();
+        assert!(matches!(error, SonarrError::Http(_)));
+        let chain = format!("{:?}", anyhow::Error::from(error));
+        assert!(!chain.contains("secret-key"), "{chain}");
+    }
+
+    #[test]
+    fn corrupt_cache_is_quarantined() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sonarr_titles.json");
+        std::fs::write(&path, "{\"version\": 2, \"entries\": ").unwrap();
+
+        assert!(load_cache(&path).unwrap().is_empty());
+        assert!(!path.exists());
+        assert!(dir.path().join("sonarr_titles.json.corrupt").exists());
+    }
+
+    #[test]
+    fn legacy_cache_is_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sonarr_titles.json");
+        std::fs::write(&path, r#"{"81797": "One Piece"}"#).unwrap();
+
+        let entries = load_cache(&path).unwrap();
+        assert_eq!(entries[&81797].title, "One Piece");
+        assert_eq!(entries[&81797].year, None);
+
+        std::fs::write(&path, encode_cache(&entries).unwrap()).unwrap();
+        assert_eq!(load_cache(&path).unwrap()[&81797].title, "One Piece");
+    }
+}
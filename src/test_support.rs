@@ -0,0 +1,14 @@
+//! Helpers shared by the unit tests.
+
+use axum::Router;
+use tokio::net::TcpListener;
+use url::Url;
+
+/// Serves `router` on an ephemeral localhost port for the rest of the test and returns
+/// its root URL, standing in for an upstream such as releases.moe or Sonarr.
+pub async fn serve(router: Router) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    Url::parse(&format!("http://{addr}/")).unwrap()
+}
@@ -0,0 +1,144 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+/// Version written to the `version` field of persisted title caches.
+const CACHE_FILE_VERSION: u32 = 2;
+
+/// A title resolved from Sonarr or Radarr, as persisted in the title cache files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedTitle {
+    pub title: String,
+    #[serde(default)]
+    pub year: Option<u32>,
+    /// Seconds since the Unix epoch when the title was fetched.
+    #[serde(default)]
+    pub fetched_at: u64,
+}
+
+impl CachedTitle {
+    pub fn new(title: String, year: Option<u32>) -> Self {
+        Self {
+            title,
+            year,
+            fetched_at: unix_now(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CacheFileRef<'a> {
+    version: u32,
+    entries: &'a HashMap<i64, CachedTitle>,
+}
+
+#[derive(Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<i64, CachedTitle>,
+}
+
+pub fn encode_cache(entries: &HashMap<i64, CachedTitle>) -> serde_json::Result<Vec<u8>> {
+    serde_json::to_vec_pretty(&CacheFileRef {
+        version: CACHE_FILE_VERSION,
+        entries,
+    })
+}
+
+/// Reads a versioned cache file, migrating the unversioned `{id: value}` layout written by
+/// earlier releases through `migrate`.
+pub fn decode_cache<L: DeserializeOwned>(
+    bytes: &[u8],
+    migrate: impl Fn(L) -> CachedTitle,
+) -> serde_json::Result<HashMap<i64, CachedTitle>> {
+    match serde_json::from_slice::<CacheFile>(bytes) {
+        Ok(file) if file.version <= CACHE_FILE_VERSION => Ok(file.entries),
+        Ok(file) => Err(serde::de::Error::custom(format!(
+            "unsupported title cache version {}",
+            file.version
+        ))),
+        Err(error) => match serde_json::from_slice::<HashMap<i64, L>>(bytes) {
+            Ok(legacy) => Ok(legacy
+                .into_iter()
+                .map(|(id, value)| (id, migrate(value)))
+                .collect()),
+            Err(_) => Err(error),
+        },
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Id-keyed title cache that evicts the least recently used entry once `max_entries` is
+/// reached, so long-running instances don't accumulate titles indefinitely.
+#[derive(Debug, Clone)]
+pub struct TitleCache<V, K = i64> {
+    entries: HashMap<K, V>,
+    recency: VecDeque<K>,
+    max_entries: usize,
+}
+
+impl<V: Clone, K: Copy + Eq + Hash> TitleCache<V, K> {
+    /// Wraps entries loaded from disk. Their order isn't persisted, so when the file holds
+    /// more than `max_entries` an arbitrary surplus is dropped.
+    pub fn from_entries(entries: HashMap<K, V>, max_entries: usize) -> Self {
+        let max_entries = max_entries.max(1);
+        let mut cache = Self {
+            entries: HashMap::with_capacity(entries.len().min(max_entries)),
+            recency: VecDeque::new(),
+            max_entries,
+        };
+        for (id, value) in entries.into_iter().take(max_entries) {
+            cache.insert(id, value);
+        }
+        cache
+    }
+
+    pub fn get(&mut self, id: K) -> Option<V> {
+        let value = self.entries.get(&id)?.clone();
+        self.touch(id);
+        Some(value)
+    }
+
+    pub fn insert(&mut self, id: K, value: V) {
+        if self.entries.insert(id, value).is_some() {
+            self.touch(id);
+            return;
+        }
+
+        self.recency.push_back(id);
+        while self.entries.len() > self.max_entries {
+            let Some(oldest) = self.recency.pop_front() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        self.entries.retain(|id, _| keep(id));
+        self.recency.retain(|id| keep(id));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn entries(&self) -> &HashMap<K, V> {
+        &self.entries
+    }
+
+    fn touch(&mut self, id: K) {
+        if let Some(position) = self.recency.iter().position(|existing| *existing == id) {
+            self.recency.remove(position);
+        }
+        self.recency.push_back(id);
+    }
+}
@@ -1,13 +1,36 @@
-use quick_xml::Writer;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, Write};
+
+use axum::body::{Body, Bytes};
 use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
 use thiserror::Error;
 use time::{OffsetDateTime, format_description::well_known::Rfc2822};
+use tokio::sync::mpsc;
 
 #[derive(Debug, Clone)]
 pub struct ChannelMetadata {
     pub title: String,
     pub description: String,
     pub site_link: String,
+    pub default_limit: usize,
+    pub max_limit: usize,
+    /// Used as `pubDate` for items without a releases.moe timestamp. Only affects how
+    /// clients display and sort the item, not whether it is grabbed.
+    pub fallback_published: Option<OffsetDateTime>,
+    /// Category tree advertised in caps.
+    pub categories: Vec<TorznabCategory>,
+    pub searching: SearchingCaps,
+}
+
+/// Parameters each search function accepts; `None` advertises the function as unavailable.
+#[derive(Debug, Clone)]
+pub struct SearchingCaps {
+    pub search: Option<Vec<&'static str>>,
+    pub tv_search: Option<Vec<&'static str>>,
+    pub movie_search: Option<Vec<&'static str>>,
 }
 
 #[derive(Debug, Clone)]
@@ -22,6 +45,110 @@ pub struct TorznabItem {
     pub seeders: u32,
     pub leechers: u32,
     pub categories: Vec<u32>,
+    /// Season the item was matched for on tv-search, emitted as a core `season` attr.
+    pub season: Option<u32>,
+    /// AniList genres, emitted as one comma-separated `genre` extended attr.
+    pub genres: Vec<String>,
+    /// `downloadvolumefactor` attr; `0` marks the item as freeleech so *arr profiles prefer it.
+    pub download_volume_factor: Option<u32>,
+    /// Set for season packs that also carry specials; emitted as a `specials` attr.
+    pub mixes_specials: bool,
+}
+
+impl TorznabItem {
+    /// Starts an item with no comments, timestamp, size, infohash or categories, and zero
+    /// peers; set the rest through the builder.
+    pub fn builder(
+        guid: impl Into<String>,
+        title: impl Into<String>,
+        link: impl Into<String>,
+    ) -> TorznabItemBuilder {
+        TorznabItemBuilder {
+            item: TorznabItem {
+                title: title.into(),
+                guid: guid.into(),
+                link: link.into(),
+                comments: None,
+                published: None,
+                size_bytes: 0,
+                info_hash: None,
+                seeders: 0,
+                leechers: 0,
+                categories: Vec::new(),
+                season: None,
+                genres: Vec::new(),
+                download_volume_factor: None,
+                mixes_specials: false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TorznabItemBuilder {
+    item: TorznabItem,
+}
+
+impl TorznabItemBuilder {
+    pub fn guid(mut self, guid: impl Into<String>) -> Self {
+        self.item.guid = guid.into();
+        self
+    }
+
+    pub fn comments(mut self, comments: Option<String>) -> Self {
+        self.item.comments = comments;
+        self
+    }
+
+    pub fn published(mut self, published: Option<OffsetDateTime>) -> Self {
+        self.item.published = published;
+        self
+    }
+
+    pub fn size_bytes(mut self, size_bytes: u64) -> Self {
+        self.item.size_bytes = size_bytes;
+        self
+    }
+
+    pub fn info_hash(mut self, info_hash: Option<String>) -> Self {
+        self.item.info_hash = info_hash;
+        self
+    }
+
+    pub fn peers(mut self, seeders: u32, leechers: u32) -> Self {
+        self.item.seeders = seeders;
+        self.item.leechers = leechers;
+        self
+    }
+
+    pub fn categories(mut self, categories: Vec<u32>) -> Self {
+        self.item.categories = categories;
+        self
+    }
+
+    pub fn season(mut self, season: Option<u32>) -> Self {
+        self.item.season = season;
+        self
+    }
+
+    pub fn genres(mut self, genres: Vec<String>) -> Self {
+        self.item.genres = genres;
+        self
+    }
+
+    pub fn download_volume_factor(mut self, factor: Option<u32>) -> Self {
+        self.item.download_volume_factor = factor;
+        self
+    }
+
+    pub fn mixes_specials(mut self, mixes_specials: bool) -> Self {
+        self.item.mixes_specials = mixes_specials;
+        self
+    }
+
+    pub fn build(self) -> TorznabItem {
+        self.item
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -55,6 +182,100 @@ pub const MOVIE_CATEGORY: TorznabCategory = TorznabCategory {
     subcategories: &[],
 };
 
+/// Which page an item's `<comments>` links to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommentsSource {
+    /// The tracker page the torrent was published on.
+    #[default]
+    Nyaa,
+    /// The releases.moe entry, with SeaDex's comparisons and notes.
+    Seadex,
+}
+
+impl CommentsSource {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "nyaa" => Some(Self::Nyaa),
+            "seadex" => Some(Self::Seadex),
+            _ => None,
+        }
+    }
+}
+
+/// What an item's `<guid>` is derived from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GuidSource {
+    /// The releases.moe record id. Changes if releases.moe re-imports the torrent.
+    #[default]
+    Id,
+    /// The lowercased infohash, stable across re-imports; records without one keep the id.
+    InfoHash,
+}
+
+impl GuidSource {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "id" => Some(Self::Id),
+            "infohash" => Some(Self::InfoHash),
+            _ => None,
+        }
+    }
+}
+
+/// Which extended torznab attrs a client asked for via `attrs`/`extended`.
+///
+/// Core attrs (`category`, `seeders`, `leechers`, `tag`, `season`) are always emitted;
+/// `season` is core because Sonarr sends neither `attrs` nor `extended` and matches season
+/// packs more reliably with it. The extended `genre` attr is only emitted when requested
+/// with `extended=1`, `attrs=all` or by name in `attrs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrSelection {
+    All,
+    Only(Vec<String>),
+}
+
+impl AttrSelection {
+    pub fn from_params(attrs: Option<&str>, extended: Option<&str>) -> Self {
+        if extended.is_some_and(|value| value.trim() == "1") {
+            return Self::All;
+        }
+
+        let names: Vec<String> = attrs
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+
+        if names.iter().any(|name| name == "all") {
+            Self::All
+        } else {
+            Self::Only(names)
+        }
+    }
+
+    pub fn includes(&self, name: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(names) => names.iter().any(|candidate| candidate == name),
+        }
+    }
+
+    /// Clears the extended attrs that weren't requested.
+    pub fn apply(&self, mut items: Vec<TorznabItem>) -> Vec<TorznabItem> {
+        if *self == Self::All {
+            return items;
+        }
+
+        for item in &mut items {
+            if !self.includes("genre") {
+                item.genres.clear();
+            }
+        }
+        items
+    }
+}
+
 pub fn default_categories() -> Vec<TorznabCategory> {
     vec![ANIME_CATEGORY, MOVIE_CATEGORY]
 }
@@ -83,9 +304,11 @@ pub fn render_caps(metadata: &ChannelMetadata) -> Result<String, TorznabBuildErr
     server.push_attribute(("version", env!("CARGO_PKG_VERSION")));
     writer.write_event(Event::Empty(server))?;
 
+    let default_limit = metadata.default_limit.to_string();
+    let max_limit = metadata.max_limit.to_string();
     let mut limits = BytesStart::new("limits");
-    limits.push_attribute(("default", "100"));
-    limits.push_attribute(("max", "100"));
+    limits.push_attribute(("default", default_limit.as_str()));
+    limits.push_attribute(("max", max_limit.as_str()));
     limits.push_attribute(("min", "1"));
     writer.write_event(Event::Empty(limits))?;
 
@@ -96,31 +319,34 @@ pub fn render_caps(metadata: &ChannelMetadata) -> Result<String, TorznabBuildErr
 
     writer.write_event(Event::Start(BytesStart::new("searching")))?;
 
-    let mut search_el = BytesStart::new("search");
-    search_el.push_attribute(("available", "yes"));
-    writer.write_event(Event::Empty(search_el))?;
-
-    let mut tv_search_el = BytesStart::new("tv-search");
-    tv_search_el.push_attribute(("available", "yes"));
-    tv_search_el.push_attribute(("supportedParams", "tvdbid,season"));
-    writer.write_event(Event::Empty(tv_search_el))?;
-
-    let mut movie_search_el = BytesStart::new("movie-search");
-    movie_search_el.push_attribute(("available", "yes"));
-    movie_search_el.push_attribute(("supportedParams", "tmdbid"));
-    writer.write_event(Event::Empty(movie_search_el))?;
+    let searching = &metadata.searching;
+    for (name, params) in [
+        ("search", &searching.search),
+        ("tv-search", &searching.tv_search),
+        ("movie-search", &searching.movie_search),
+    ] {
+        let mut search_el = BytesStart::new(name);
+        match params {
+            Some(params) => {
+                search_el.push_attribute(("available", "yes"));
+                search_el.push_attribute(("supportedParams", params.join(",").as_str()));
+            }
+            None => search_el.push_attribute(("available", "no")),
+        }
+        writer.write_event(Event::Empty(search_el))?;
+    }
 
     writer.write_event(Event::End(BytesEnd::new("searching")))?;
 
     writer.write_event(Event::Start(BytesStart::new("categories")))?;
 
-    for category in default_categories() {
+    for category in &metadata.categories {
         let id_attr = category.id.to_string();
         let mut category_el = BytesStart::new("category");
         category_el.push_attribute(("id", id_attr.as_str()));
         category_el.push_attribute(("name", category.name));
 
-        if category.subcategories.len() > 0 {
+        if !category.subcategories.is_empty() {
             writer.write_event(Event::Start(category_el))?;
 
             for sub in category.subcategories {
@@ -152,77 +378,332 @@ pub fn render_caps(metadata: &ChannelMetadata) -> Result<String, TorznabBuildErr
     Ok(String::from_utf8(writer.into_inner())?)
 }
 
+/// Checks a rendered caps document for the things Prowlarr rejects without a useful
+/// message. Returns one warning per problem; an empty list means the caps look valid.
+pub fn validate_caps(xml: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut reader = Reader::from_str(xml);
+    let mut root: Option<String> = None;
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut categories = 0usize;
+
+    loop {
+        let element = match reader.read_event() {
+            Ok(Event::Start(element) | Event::Empty(element)) => element,
+            Ok(Event::Eof) => break,
+            Ok(_) => continue,
+            Err(error) => {
+                warnings.push(format!("caps is not well-formed XML: {error}"));
+                return warnings;
+            }
+        };
+
+        let name = String::from_utf8_lossy(element.name().as_ref()).into_owned();
+        let attrs: HashMap<String, String> = element
+            .attributes()
+            .flatten()
+            .map(|attr| {
+                let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+                let value = attr
+                    .unescape_value()
+                    .map(Cow::into_owned)
+                    .unwrap_or_default();
+                (key, value)
+            })
+            .collect();
+        root.get_or_insert_with(|| name.clone());
+
+        match name.as_str() {
+            "limits" => {
+                let default = attrs
+                    .get("default")
+                    .and_then(|value| value.parse::<usize>().ok());
+                let max = attrs
+                    .get("max")
+                    .and_then(|value| value.parse::<usize>().ok());
+                for (key, value) in [("default", default), ("max", max)] {
+                    if value.is_none_or(|value| value == 0) {
+                        warnings.push(format!("<limits> {key} must be a positive integer"));
+                    }
+                }
+                if let (Some(default), Some(max)) = (default, max)
+                    && default > max
+                {
+                    warnings.push(format!("<limits> default {default} exceeds max {max}"));
+                }
+            }
+            "search" | "tv-search" | "movie-search" => {
+                match attrs.get("available").map(String::as_str) {
+                    Some("yes") => {
+                        let params = attrs.get("supportedParams").map(String::as_str);
+                        if params.is_none_or(|params| params.trim().is_empty()) {
+                            warnings.push(format!(
+                                "<{name}> is available but lists no supportedParams"
+                            ));
+                        }
+                    }
+                    Some("no") => {}
+                    other => warnings.push(format!(
+                        "<{name}> available must be \"yes\" or \"no\", got {other:?}"
+                    )),
+                }
+            }
+            "category" | "subcat" => {
+                if name == "category" {
+                    categories += 1;
+                }
+                match attrs.get("id") {
+                    Some(id) if id.parse::<u32>().is_ok() => {}
+                    Some(id) => warnings.push(format!("<{name}> id {id:?} is not numeric")),
+                    None => warnings.push(format!("<{name}> is missing an id")),
+                }
+                if attrs
+                    .get("name")
+                    .is_none_or(|value| value.trim().is_empty())
+                {
+                    warnings.push(format!("<{name}> is missing a name"));
+                }
+            }
+            _ => {}
+        }
+
+        seen.insert(name);
+    }
+
+    if root.as_deref() != Some("caps") {
+        warnings.push(format!("root element must be <caps>, got {root:?}"));
+    }
+    for required in ["server", "limits", "searching", "search", "categories"] {
+        if !seen.contains(required) {
+            warnings.push(format!("missing required <{required}> element"));
+        }
+    }
+    if seen.contains("categories") && categories == 0 {
+        warnings.push("<categories> advertises no categories".to_string());
+    }
+
+    warnings
+}
+
+/// Renders a newznab `<error>` document, e.g. code 201 for an incorrect parameter or 900
+/// for an unknown error.
+pub fn render_error(code: u16, description: &str) -> Result<String, TorznabBuildError> {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+
+    let code = code.to_string();
+    let mut error = BytesStart::new("error");
+    error.push_attribute(("code", code.as_str()));
+    error.push_attribute(("description", description));
+    writer.write_event(Event::Empty(error))?;
+
+    Ok(String::from_utf8(writer.into_inner())?)
+}
+
+/// Weak ETag over what distinguishes one rendering of a feed page from another: the item
+/// guids and publish dates plus the paging window.
+pub fn feed_etag(items: &[TorznabItem], offset: usize, total: usize) -> String {
+    let mut hasher = DefaultHasher::new();
+    offset.hash(&mut hasher);
+    total.hash(&mut hasher);
+    for item in items {
+        item.guid.hash(&mut hasher);
+        item.published
+            .map(|published| published.unix_timestamp())
+            .hash(&mut hasher);
+    }
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
 pub fn render_feed(
     metadata: &ChannelMetadata,
     items: &[TorznabItem],
-    _offset: usize,
-    _total: usize,
+    offset: usize,
+    total: usize,
 ) -> Result<String, TorznabBuildError> {
     let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    write_feed_head(&mut writer, metadata, offset, total)?;
+    for item in items {
+        write_feed_item(&mut writer, metadata, item)?;
+    }
+    write_feed_tail(&mut writer)?;
+
+    Ok(String::from_utf8(writer.into_inner())?)
+}
+
+/// Renders the same document as [`render_feed`] on a blocking thread, handing it to the
+/// returned body in `STREAM_CHUNK_BYTES` chunks as items are written. Peak memory stays
+/// at roughly one chunk per in-flight send instead of the whole document.
+pub fn stream_feed(
+    metadata: ChannelMetadata,
+    items: Vec<TorznabItem>,
+    offset: usize,
+    total: usize,
+) -> Body {
+    let (tx, mut rx) = mpsc::channel::<io::Result<Bytes>>(STREAM_CHANNEL_DEPTH);
+
+    tokio::task::spawn_blocking(move || {
+        let mut writer = Writer::new_with_indent(ChunkWriter::new(tx.clone()), b' ', 2);
+        let result = write_feed_head(&mut writer, &metadata, offset, total)
+            .and_then(|()| {
+                items
+                    .iter()
+                    .try_for_each(|item| write_feed_item(&mut writer, &metadata, item))
+            })
+            .and_then(|()| write_feed_tail(&mut writer))
+            .and_then(|()| writer.get_mut().flush().map_err(TorznabBuildError::from));
+
+        if let Err(error) = result {
+            // The client has already received a partial document; failing the body
+            // aborts the response instead of ending it with truncated XML.
+            tracing::warn!(error = %error, "failed to stream torznab feed");
+            let _ = tx.blocking_send(Err(io::Error::other(error.to_string())));
+        }
+    });
+
+    Body::from_stream(futures_util::stream::poll_fn(move |cx| rx.poll_recv(cx)))
+}
+
+const STREAM_CHUNK_BYTES: usize = 16 * 1024;
+const STREAM_CHANNEL_DEPTH: usize = 4;
+
+/// `io::Write` adapter that forwards full chunks to a streaming response body.
+struct ChunkWriter {
+    buffer: Vec<u8>,
+    tx: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl ChunkWriter {
+    fn new(tx: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        Self {
+            buffer: Vec::with_capacity(STREAM_CHUNK_BYTES),
+            tx,
+        }
+    }
+
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(STREAM_CHUNK_BYTES));
+        self.tx
+            .blocking_send(Ok(Bytes::from(chunk)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "feed receiver dropped"))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= STREAM_CHUNK_BYTES {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+fn write_feed_head<W: Write>(
+    writer: &mut Writer<W>,
+    metadata: &ChannelMetadata,
+    offset: usize,
+    total: usize,
+) -> Result<(), TorznabBuildError> {
     writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
 
     let mut rss = BytesStart::new("rss");
     rss.push_attribute(("version", "2.0"));
     rss.push_attribute(("xmlns:torznab", "http://torznab.com/schemas/2015/feed"));
+    rss.push_attribute((
+        "xmlns:newznab",
+        "http://www.newznab.com/DTD/2010/feeds/attributes/",
+    ));
     writer.write_event(Event::Start(rss))?;
 
     writer.write_event(Event::Start(BytesStart::new("channel")))?;
-    write_text_element(&mut writer, "title", &metadata.title)?;
-    write_text_element(&mut writer, "description", &metadata.description)?;
-    write_text_element(&mut writer, "link", &metadata.site_link)?;
-
-    for item in items.iter() {
-        writer.write_event(Event::Start(BytesStart::new("item")))?;
-        write_text_element(&mut writer, "title", &item.title)?;
-        write_text_element(&mut writer, "guid", &item.guid)?;
-        write_text_element(&mut writer, "link", &item.link)?;
+    write_text_element(writer, "title", &metadata.title)?;
+    write_text_element(writer, "description", &metadata.description)?;
+    write_text_element(writer, "link", &metadata.site_link)?;
+
+    let mut response = BytesStart::new("newznab:response");
+    response.push_attribute(("offset", offset.to_string().as_str()));
+    response.push_attribute(("total", total.to_string().as_str()));
+    writer.write_event(Event::Empty(response))?;
+    Ok(())
+}
 
-        if let Some(comments) = item.comments.as_deref() {
-            write_text_element(&mut writer, "comments", comments)?;
-        }
+fn write_feed_item<W: Write>(
+    writer: &mut Writer<W>,
+    metadata: &ChannelMetadata,
+    item: &TorznabItem,
+) -> Result<(), TorznabBuildError> {
+    writer.write_event(Event::Start(BytesStart::new("item")))?;
+    write_text_element(writer, "title", &item.title)?;
+    write_text_element(writer, "guid", &item.guid)?;
+    write_text_element(writer, "link", &item.link)?;
+
+    if let Some(comments) = item.comments.as_deref() {
+        write_text_element(writer, "comments", comments)?;
+    }
 
-        if let Some(published) = item.published {
-            let formatted = published.format(&Rfc2822)?;
-            write_text_element(&mut writer, "pubDate", &formatted)?;
-        }
+    if let Some(published) = item.published.or(metadata.fallback_published) {
+        let formatted = published.format(&Rfc2822)?;
+        write_text_element(writer, "pubDate", &formatted)?;
+    }
 
-        write_text_element(&mut writer, "size", &item.size_bytes.to_string())?;
+    write_text_element(writer, "size", &item.size_bytes.to_string())?;
 
-        if let Some(info_hash) = item.info_hash.as_deref() {
-            write_text_element(&mut writer, "infohash", info_hash)?;
-        }
+    if let Some(info_hash) = item.info_hash.as_deref() {
+        write_text_element(writer, "infohash", info_hash)?;
+    }
 
-        let mut enclosure = BytesStart::new("enclosure");
-        enclosure.push_attribute(("url", item.link.as_str()));
-        enclosure.push_attribute(("type", "application/x-bittorrent"));
-        enclosure.push_attribute(("length", item.size_bytes.to_string().as_str()));
-        writer.write_event(Event::Empty(enclosure))?;
+    let mut enclosure = BytesStart::new("enclosure");
+    enclosure.push_attribute(("url", item.link.as_str()));
+    enclosure.push_attribute(("type", "application/x-bittorrent"));
+    enclosure.push_attribute(("length", item.size_bytes.to_string().as_str()));
+    writer.write_event(Event::Empty(enclosure))?;
 
-        if item.categories.is_empty() {
-            write_attr(&mut writer, "category", &ANIME_CATEGORY.id.to_string())?;
-            if let Some(sub) = ANIME_CATEGORY.subcategories.first() {
-                write_attr(&mut writer, "category", &sub.id.to_string())?;
-            }
-        } else {
-            for category_id in &item.categories {
-                write_attr(&mut writer, "category", &category_id.to_string())?;
-            }
+    if item.categories.is_empty() {
+        write_attr(writer, "category", &ANIME_CATEGORY.id.to_string())?;
+        if let Some(sub) = ANIME_CATEGORY.subcategories.first() {
+            write_attr(writer, "category", &sub.id.to_string())?;
         }
-        write_attr(&mut writer, "seeders", &item.seeders.to_string())?;
-        write_attr(&mut writer, "leechers", &item.leechers.to_string())?;
-        write_attr(&mut writer, "tag", TAG)?;
-
-        writer.write_event(Event::End(BytesEnd::new("item")))?;
+    } else {
+        for category_id in &item.categories {
+            write_attr(writer, "category", &category_id.to_string())?;
+        }
+    }
+    if let Some(season) = item.season {
+        write_attr(writer, "season", &season.to_string())?;
+    }
+    if !item.genres.is_empty() {
+        write_attr(writer, "genre", &item.genres.join(", "))?;
+    }
+    write_attr(writer, "seeders", &item.seeders.to_string())?;
+    write_attr(writer, "leechers", &item.leechers.to_string())?;
+    if let Some(factor) = item.download_volume_factor {
+        write_attr(writer, "downloadvolumefactor", &factor.to_string())?;
+    }
+    if item.mixes_specials {
+        write_attr(writer, "specials", "1")?;
     }
+    write_attr(writer, "tag", TAG)?;
 
+    writer.write_event(Event::End(BytesEnd::new("item")))?;
+    Ok(())
+}
+
+fn write_feed_tail<W: Write>(writer: &mut Writer<W>) -> Result<(), TorznabBuildError> {
     writer.write_event(Event::End(BytesEnd::new("channel")))?;
     writer.write_event(Event::End(BytesEnd::new("rss")))?;
-
-    Ok(String::from_utf8(writer.into_inner())?)
+    Ok(())
 }
 
-fn write_text_element(
-    writer: &mut Writer<Vec<u8>>,
+fn write_text_element<W: Write>(
+    writer: &mut Writer<W>,
     name: &str,
     value: &str,
 ) -> Result<(), quick_xml::Error> {
@@ -232,8 +713,8 @@ fn write_text_element(
     Ok(())
 }
 
-fn write_attr(
-    writer: &mut Writer<Vec<u8>>,
+fn write_attr<W: Write>(
+    writer: &mut Writer<W>,
     name: &str,
     value: &str,
 ) -> Result<(), quick_xml::Error> {
@@ -243,3 +724,76 @@ fn write_attr(
     writer.write_event(Event::Empty(attr))?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(fallback_published: Option<OffsetDateTime>) -> ChannelMetadata {
+        ChannelMetadata {
+            title: "Seadexer".to_owned(),
+            description: String::new(),
+            site_link: "http://localhost:6767/".to_owned(),
+            default_limit: 100,
+            max_limit: 100,
+            fallback_published,
+            categories: default_categories(),
+            searching: SearchingCaps {
+                search: None,
+                tv_search: None,
+                movie_search: None,
+            },
+        }
+    }
+
+    #[test]
+    fn attr_selection_defaults_to_core_attrs() {
+        assert_eq!(
+            AttrSelection::from_params(None, None),
+            AttrSelection::Only(Vec::new())
+        );
+        assert_eq!(
+            AttrSelection::from_params(Some(" , "), None),
+            AttrSelection::Only(Vec::new())
+        );
+        assert_eq!(
+            AttrSelection::from_params(Some("all"), None),
+            AttrSelection::All
+        );
+        assert_eq!(
+            AttrSelection::from_params(Some("genre"), Some("1")),
+            AttrSelection::All
+        );
+    }
+
+    #[test]
+    fn attr_selection_clears_unrequested_attrs() {
+        let item = TorznabItem::builder("guid", "title", "link")
+            .season(Some(1))
+            .genres(vec!["Action".to_owned()])
+            .build();
+
+        let items = AttrSelection::from_params(None, None).apply(vec![item.clone()]);
+        assert_eq!(items[0].season, Some(1));
+        assert!(items[0].genres.is_empty());
+
+        let items = AttrSelection::from_params(Some("Genre"), None).apply(vec![item]);
+        assert_eq!(items[0].season, Some(1));
+        assert_eq!(items[0].genres, ["Action"]);
+    }
+
+    #[test]
+    fn items_without_a_timestamp_use_the_fallback_pubdate() {
+        let items = [TorznabItem::builder("guid", "title", "link").build()];
+
+        let fallback = OffsetDateTime::from_unix_timestamp(1_704_067_200).unwrap();
+        let xml = render_feed(&metadata(Some(fallback)), &items, 0, 1).unwrap();
+        assert!(
+            xml.contains("<pubDate>Mon, 01 Jan 2024 00:00:00 +0000</pubDate>"),
+            "{xml}"
+        );
+
+        let xml = render_feed(&metadata(None), &items, 0, 1).unwrap();
+        assert!(!xml.contains("<pubDate>"), "{xml}");
+    }
+}
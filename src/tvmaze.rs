@@ -0,0 +1,99 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use reqwest::{Client, StatusCode, Url};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::config::OutboundConfig;
+use crate::title_cache::TitleCache;
+
+/// Most show id lookups kept; least recently used are evicted.
+const CACHE_MAX_ENTRIES: usize = 1_000;
+
+/// A show id from a database other than tvdb that TVmaze can cross-reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExternalShowId {
+    TvMaze(i64),
+    TvRage(i64),
+}
+
+/// Resolves TVmaze and TVRage show ids to tvdb ids via TVmaze's `externals`, for clients
+/// that search by `tvmazeid` or `rid`.
+#[derive(Debug, Clone)]
+pub struct TvMazeClient {
+    http: Client,
+    base_url: Url,
+    cache: Arc<Mutex<TitleCache<Option<i64>, ExternalShowId>>>,
+}
+
+impl TvMazeClient {
+    pub fn new(
+        base_url: Url,
+        timeout: Duration,
+        outbound: &OutboundConfig,
+    ) -> anyhow::Result<Self> {
+        let http = outbound.client_builder(timeout)?.build()?;
+
+        Ok(Self {
+            http,
+            base_url,
+            cache: Arc::new(Mutex::new(TitleCache::from_entries(
+                HashMap::new(),
+                CACHE_MAX_ENTRIES,
+            ))),
+        })
+    }
+
+    /// Returns the tvdb id TVmaze lists for the show, or `None` when the show is unknown
+    /// or has no tvdb cross-reference.
+    pub async fn resolve_tvdb_id(&self, id: ExternalShowId) -> Result<Option<i64>, TvMazeError> {
+        if let Some(tvdb_id) = self.cache.lock().await.get(id) {
+            return Ok(tvdb_id);
+        }
+
+        let url = match id {
+            ExternalShowId::TvMaze(tvmaze_id) => self.base_url.join(&format!("shows/{tvmaze_id}")),
+            ExternalShowId::TvRage(rid) => self.base_url.join("lookup/shows").map(|mut url| {
+                url.query_pairs_mut()
+                    .append_pair("tvrage", &rid.to_string());
+                url
+            }),
+        }
+        .map_err(TvMazeError::Url)?;
+
+        debug!(id = ?id, url = %url, "resolving tvdb id via TVmaze");
+
+        let response = self.http.get(url).send().await?;
+        let tvdb_id = if response.status() == StatusCode::NOT_FOUND {
+            None
+        } else {
+            let show: ShowResponse = response.error_for_status()?.json().await?;
+            show.externals.thetvdb
+        };
+
+        self.cache.lock().await.insert(id, tvdb_id);
+        Ok(tvdb_id)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ShowResponse {
+    #[serde(default)]
+    externals: ShowExternals,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ShowExternals {
+    #[serde(default)]
+    thetvdb: Option<i64>,
+}
+
+#[derive(Debug, Error)]
+pub enum TvMazeError {
+    #[error("failed to build TVmaze request url")]
+    Url(#[from] url::ParseError),
+    #[error("http error when querying TVmaze")]
+    Http(#[from] reqwest::Error),
+}